@@ -1,5 +1,15 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::line_index::LineIndex;
+use crate::mark::Marks;
+use crate::snapshot::{Shared, Snapshot};
+use crate::storage::TextStorage;
+
 pub const DEFAULT_GAP_LEN: usize = 1024;
 const NULL: u8 = b'\0';
+// most bytes a snapshot block is copied in at once
+const SNAPSHOT_BLOCK_LEN: usize = 64 * 1024;
 
 #[derive(Debug)]
 pub struct Buffer {
@@ -9,11 +19,26 @@ pub struct Buffer {
     // iptr -> points to the first slot of the gap
     // iptr - 1 -> points to the last byte before the gap
     // iptr + gap_len -> points to the first byte after the gap
-    pub iptr: usize,
+    iptr: usize,
     gap_len: usize,
     // since `char` has a fixed size of 4-byte,
     // using `u8` is more memory efficient, but it has to parse the bytes to char manually
     bytes: Vec<u8>,
+    // newline offsets, kept in sync by every method that changes the text
+    lines: LineIndex,
+    marks: Marks,
+    version: u64,
+    // copy-on-write copy of the text for snapshots, in order
+    // an edit only drops the bytes of the blocks it touches, the next snapshot copies
+    // those again and shares the others with the previous snapshots
+    blocks: Vec<Block>,
+}
+
+#[derive(Debug)]
+struct Block {
+    len: usize,
+    // none until a snapshot needs it after an edit
+    bytes: Option<Shared>,
 }
 
 #[derive(Debug)]
@@ -24,13 +49,11 @@ pub struct BufferIter<'a> {
 
 impl<'a> BufferIter<'a> {
     pub fn seek(&mut self, i: usize) {
-        let mut new_iter = self.buf.iter();
-
-        for _ in 0..i {
-            new_iter.next();
-        }
-
-        *self = new_iter;
+        self.current = if i < self.buf.iptr {
+            i
+        } else {
+            (i + self.buf.gap_len).min(self.buf.bytes.len())
+        };
     }
 }
 
@@ -67,48 +90,134 @@ impl Buffer {
     pub fn init(mut s: String) -> Self {
         let mut buffer = Vec::with_capacity(DEFAULT_GAP_LEN + s.len());
         buffer.append(&mut vec![0u8; DEFAULT_GAP_LEN]);
+        let lines = LineIndex::new(s.as_bytes());
+        let blocks = if s.is_empty() {
+            Vec::new()
+        } else {
+            vec![Block {
+                len: s.len(),
+                bytes: None,
+            }]
+        };
 
         unsafe {
-            buffer.append(&mut s.as_mut_vec());
+            buffer.append(s.as_mut_vec());
         }
 
         Self {
             bytes: buffer,
             iptr: 0,
             gap_len: DEFAULT_GAP_LEN,
+            lines,
+            marks: Marks::new(),
+            version: 0,
+            blocks,
+        }
+    }
+
+    // makes sure the gap can hold `n` more bytes
+    // when the gap is less than 25% of the current buffer, double the buffer size
+    // this increases the gap size to = current gap size + previous buffer size
+    fn reserve(&mut self, n: usize) {
+        if self.gap_len >= n && self.gap_len >= self.bytes.len() / 4 {
+            return;
         }
+
+        let old_len = self.bytes.len();
+        let mut new_len = old_len * 2;
+        while self.gap_len + new_len - old_len < n {
+            new_len *= 2;
+        }
+
+        let after = self.iptr + self.gap_len;
+        self.bytes.resize(new_len, NULL);
+        self.bytes
+            .copy_within(after..old_len, after + new_len - old_len);
+        self.bytes[after..after + new_len - old_len].fill(NULL);
+
+        self.gap_len += new_len - old_len;
     }
 
-    pub fn iter(&self) -> BufferIter<'_> {
+    fn iter(&self) -> BufferIter<'_> {
         BufferIter {
             buf: self,
             current: 0,
         }
     }
 
-    pub fn jump(&mut self, n: usize) {
-        if n < self.iptr {
-            for _ in 0..self.iptr - n {
-                self.move_ptr_backward();
+    fn before_insertion_point(&self) -> &[u8] {
+        &self.bytes[..self.iptr]
+    }
+
+    fn after_insertion_point(&self) -> &[u8] {
+        &self.bytes[self.iptr + self.gap_len..]
+    }
+
+    // `n` bytes were inserted at `i`, the block around them has to be copied again
+    fn insert_blocks(&mut self, i: usize, n: usize) {
+        let mut start = 0;
+        for block in &mut self.blocks {
+            if i <= start + block.len {
+                block.len += n;
+                block.bytes = None;
+                return;
             }
-        } else {
-            for _ in 0..n - self.iptr {
-                self.move_ptr_forward();
+
+            start += block.len;
+        }
+
+        self.blocks.push(Block {
+            len: n,
+            bytes: None,
+        });
+    }
+
+    // `range` is about to be deleted, the blocks it overlaps have to be copied again
+    fn delete_blocks(&mut self, range: Range<usize>) {
+        let mut start = 0;
+        for block in &mut self.blocks {
+            let end = start + block.len;
+            let overlap = range.end.min(end).saturating_sub(range.start.max(start));
+            if overlap > 0 {
+                block.len -= overlap;
+                block.bytes = None;
             }
+
+            start = end;
         }
+
+        self.blocks.retain(|block| block.len > 0);
     }
+}
 
-    pub fn before_insertion_point(&self) -> &[u8] {
-        &self.bytes[..self.iptr]
+impl TextStorage for Buffer {
+    fn len(&self) -> usize {
+        self.bytes.len() - self.gap_len
     }
 
-    pub fn after_insertion_point(&self) -> &[u8] {
-        &self.bytes[self.iptr + self.gap_len..]
+    fn cursor(&self) -> usize {
+        self.iptr
+    }
+
+    // `n` is a byte offset and must be on a char boundary
+    // the bytes between the old and the new position are moved across the gap at once
+    fn jump(&mut self, n: usize) {
+        let n = n.min(self.len());
+
+        if n < self.iptr {
+            self.bytes.copy_within(n..self.iptr, n + self.gap_len);
+        } else if n > self.iptr {
+            let i = self.iptr + self.gap_len;
+            self.bytes.copy_within(i..i + n - self.iptr, self.iptr);
+        }
+
+        self.iptr = n;
     }
 
     // the first "char" after the gap is moved to the slot(s) at the beginning of the gap
     // iptr points to the next slot in the gap
-    pub fn move_ptr_forward(&mut self) {
+    #[cfg(test)]
+    fn move_ptr_forward(&mut self) {
         if self.iptr + self.gap_len == self.bytes.len() {
             return;
         }
@@ -117,7 +226,7 @@ impl Buffer {
         for j in 0..size_of::<char>() {
             self.bytes[self.iptr + j] = self.bytes[i + j];
 
-            if let Ok(_) = str::from_utf8(&self.bytes[i..=i + j]) {
+            if str::from_utf8(&self.bytes[i..=i + j]).is_ok() {
                 self.iptr += j + 1;
                 return;
             }
@@ -127,7 +236,8 @@ impl Buffer {
     }
 
     // the last "char" before the gap is moved to the slot(s) after the gap
-    pub fn move_ptr_backward(&mut self) {
+    #[cfg(test)]
+    fn move_ptr_backward(&mut self) {
         if self.iptr == 0 {
             return;
         }
@@ -135,7 +245,7 @@ impl Buffer {
         let i = self.iptr - 1;
         for j in 0..size_of::<char>() {
             self.bytes[i - j + self.gap_len] = self.bytes[i - j];
-            if let Ok(_) = str::from_utf8(&self.bytes[i - j..=i]) {
+            if str::from_utf8(&self.bytes[i - j..=i]).is_ok() {
                 self.iptr -= j + 1;
 
                 return;
@@ -145,115 +255,115 @@ impl Buffer {
         panic!("corrupted utf8");
     }
 
-    pub fn insert(&mut self, c: char) {
-        // when the gap is less than 25% of the current buffer, double the buffer size
-        // this increases the gap size to = current gap size + previous buffer size
-        if self.gap_len < self.bytes.len() / 4 {
-            let old_len = self.bytes.len();
-            self.bytes.resize(old_len * 2, NULL);
-            let new_len = self.bytes.len();
-
-            for i in (self.iptr + self.gap_len)..old_len {
-                self.bytes[i + new_len - old_len] = self.bytes[i];
-                self.bytes[i] = NULL;
-            }
-
-            self.gap_len += old_len;
-        }
-
-        assert!(
-            self.gap_len >= size_of::<char>(),
-            "not enough space for insertion"
-        );
-
-        let s = String::from(c);
+    // the gap is moved to the insertion point already, so this is a single copy into the gap
+    fn insert_str(&mut self, s: &str) {
         let s_bytes = s.as_bytes();
-        let c_len = s_bytes.len();
+        let n = s_bytes.len();
+        self.reserve(n);
 
-        for i in 0..c_len {
-            self.bytes[self.iptr + i] = s_bytes[i];
-        }
+        self.bytes[self.iptr..self.iptr + n].copy_from_slice(s_bytes);
+        self.insert_blocks(self.iptr, n);
+        self.lines.insert(self.iptr, s_bytes);
+        self.marks.insert(self.iptr, n);
 
-        self.iptr += c_len;
-        self.gap_len -= c_len;
+        self.iptr += n;
+        self.gap_len -= n;
+        self.version += 1;
     }
 
-    pub fn revert_insert(&mut self, prev_iptr: usize, n: usize) {
-        self.jump(prev_iptr);
-        self.gap_len = (self.gap_len + n).min(self.bytes.len());
+    // one gap move to `range.start`, then the gap simply swallows the deleted bytes
+    fn delete_range(&mut self, range: Range<usize>) -> Vec<u8> {
+        self.jump(range.start);
+
+        let i = self.iptr + self.gap_len;
+        let deleted = self.bytes[i..i + range.len()].to_vec();
+        self.delete_blocks(range.clone());
+        self.lines.delete(range.clone());
+        self.marks.delete(range.clone());
+        self.gap_len += range.len();
+        self.version += 1;
+
+        deleted
     }
 
-    pub fn revert_delete_before_ptr(&mut self, prev_iptr: usize, deleted: &Vec<u8>) {
-        self.jump(prev_iptr);
-        self.gap_len = self.gap_len.saturating_sub(deleted.len());
+    fn iter_from(&self, i: usize) -> Box<dyn Iterator<Item = u8> + '_> {
+        let mut iter = self.iter();
+        iter.seek(i);
 
-        let new_iptr = self.iptr + deleted.len();
-        for i in self.iptr..new_iptr {
-            self.bytes[i] = deleted[i - self.iptr];
-        }
+        Box::new(iter.copied())
+    }
 
-        self.iptr = new_iptr;
+    fn lines(&self) -> &LineIndex {
+        &self.lines
     }
 
-    pub fn delete_before_ptr(&mut self) -> Option<Vec<u8>> {
-        if self.iptr == 0 {
-            return None;
-        }
+    fn marks(&self) -> &Marks {
+        &self.marks
+    }
 
-        let mut i = self.iptr.saturating_sub(1);
-        let mut res = Vec::new();
-        for _ in 0..size_of::<char>() {
-            res.push(self.bytes[i]);
+    fn marks_mut(&mut self) -> &mut Marks {
+        &mut self.marks
+    }
 
-            if let Ok(_) = str::from_utf8(&self.bytes[i..self.iptr]) {
-                let n = self.iptr - i;
-                self.gap_len += n;
-                self.iptr = i;
+    fn version(&self) -> u64 {
+        self.version
+    }
 
-                return Some(res);
+    fn snapshot(&mut self) -> Snapshot {
+        let mut blocks = Vec::with_capacity(self.blocks.len());
+        let mut start = 0;
+        for block in std::mem::take(&mut self.blocks) {
+            let end = start + block.len;
+            if block.bytes.is_some() {
+                blocks.push(block);
+            } else {
+                for i in (start..end).step_by(SNAPSHOT_BLOCK_LEN) {
+                    let j = (i + SNAPSHOT_BLOCK_LEN).min(end);
+                    blocks.push(Block {
+                        len: j - i,
+                        bytes: Some(Arc::new(self.slice(i..j))),
+                    });
+                }
             }
 
-            i = i.saturating_sub(1);
+            start = end;
         }
+        self.blocks = blocks;
 
-        panic!("corrupted utf8");
-    }
-
-    pub fn revert_delete_after_ptr(&mut self, prev_iptr: usize, deleted: &Vec<u8>) {
-        self.jump(prev_iptr);
-        self.gap_len = self.gap_len.saturating_sub(deleted.len());
+        let slices = self
+            .blocks
+            .iter()
+            .filter_map(|block| Some((block.bytes.clone()?, 0..block.len)))
+            .collect();
 
-        for i in self.iptr..self.iptr + deleted.len() {
-            self.bytes[i + self.gap_len] = deleted[i - self.iptr];
-        }
+        Snapshot::new(slices, self.version)
     }
 
-    pub fn delete_after_ptr(&mut self) -> Option<Vec<u8>> {
-        let i = self.iptr + self.gap_len;
-        if i == self.bytes.len() {
-            return None;
+    // at most two copies, from each side of the gap
+    fn slice(&self, range: Range<usize>) -> Vec<u8> {
+        let end = range.end.min(self.len());
+        let range = range.start.min(end)..end;
+        let (before, after) = (self.before_insertion_point(), self.after_insertion_point());
+        let mut bytes = Vec::with_capacity(range.len());
+        if range.start < before.len() {
+            bytes.extend_from_slice(&before[range.start..range.end.min(before.len())]);
         }
-
-        let mut res = Vec::new();
-        for j in 0..size_of::<char>() {
-            res.push(self.bytes[i]);
-
-            if let Ok(_) = str::from_utf8(&self.bytes[i..=i + j]) {
-                self.gap_len = (self.gap_len + j + 1).min(self.bytes.len());
-                return Some(res);
-            }
+        if range.end > before.len() {
+            bytes.extend_from_slice(
+                &after[range.start.saturating_sub(before.len())..range.end - before.len()],
+            );
         }
 
-        panic!("corrupted utf8");
+        bytes
     }
 
-    #[cfg(test)]
-    pub fn to_string(&self) -> String {
-        let end = self.iptr + self.gap_len;
-        let mut b = self.bytes[..self.iptr].to_vec();
-        b.append(&mut self.bytes[end..].to_vec());
-
-        String::from_utf8(b).expect("BUG!!!")
+    // logical byte at `i`, skipping over the gap
+    fn byte_at(&self, i: usize) -> Option<u8> {
+        if i < self.iptr {
+            Some(self.bytes[i])
+        } else {
+            self.bytes.get(i + self.gap_len).copied()
+        }
     }
 }
 
@@ -586,4 +696,32 @@ mod tests {
             dbg!(i, *b);
         }
     }
+
+    #[test]
+    fn test_buf_snapshot_1() {
+        let mut buf = Buffer::init("x".repeat(3 * SNAPSHOT_BLOCK_LEN));
+        let before = buf.snapshot();
+        assert_eq!(buf.blocks.len(), 3);
+
+        // only the first block is copied again
+        buf.insert('a');
+        buf.jump(SNAPSHOT_BLOCK_LEN + 1);
+        buf.insert('b');
+        buf.delete_range(1..2);
+        let after = buf.snapshot();
+        assert_eq!(after.chunks().count(), 4);
+        assert_ne!(before.chunks().next(), after.chunks().next());
+        for (a, b) in before.chunks().skip(1).zip(after.chunks().skip(2)) {
+            assert_eq!(a.as_ptr(), b.as_ptr());
+        }
+
+        let text = after.text();
+        assert_eq!(text.len(), 3 * SNAPSHOT_BLOCK_LEN + 1);
+        assert_eq!(&text[..2], "ax");
+        assert_eq!(&text[SNAPSHOT_BLOCK_LEN - 1..SNAPSHOT_BLOCK_LEN + 2], "xbx");
+
+        buf.delete_range(0..buf.len());
+        assert!(buf.blocks.is_empty());
+        assert_eq!(buf.snapshot().text(), "");
+    }
 }
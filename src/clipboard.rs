@@ -0,0 +1,235 @@
+use std::env;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how long a clipboard tool may run before it is killed, a stalled one must not
+// freeze the editor
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+// Where killed and copied text also goes, so that other programs can paste it,
+// and where yank looks for text copied by them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Clipboard {
+    Off,
+    // an escape sequence the terminal turns into a copy, also over ssh
+    // it cannot be read back, the terminal's own paste is used instead
+    Osc52,
+    // local programs: the text is written to the input of `copy`, and read from the
+    // output of `paste`, an empty command is not run
+    Command { copy: String, paste: String },
+}
+
+impl Clipboard {
+    // the clipboard tools of the running desktop if there are some, osc52 otherwise
+    pub fn detect() -> Self {
+        let tools = [
+            ("WAYLAND_DISPLAY", "wl-copy", "wl-paste --no-newline"),
+            (
+                "DISPLAY",
+                "xclip -selection clipboard -in",
+                "xclip -selection clipboard -out",
+            ),
+            ("", "pbcopy", "pbpaste"),
+        ];
+
+        tools
+            .into_iter()
+            .find(|(var, copy, _)| {
+                (var.is_empty() || env::var_os(var).is_some()) && on_path(program(copy))
+            })
+            .map_or(Self::Osc52, |(_, copy, paste)| Self::Command {
+                copy: copy.to_string(),
+                paste: paste.to_string(),
+            })
+    }
+
+    pub fn copy(&self, s: &str) -> io::Result<()> {
+        match self {
+            Self::Off => Ok(()),
+            Self::Osc52 => {
+                let mut out = io::stdout();
+                write!(out, "\x1b]52;c;{}\x07", base64(s.as_bytes()))?;
+                out.flush()
+            }
+            Self::Command { copy, .. } if copy.is_empty() => Ok(()),
+            Self::Command { copy, .. } => {
+                let mut child = command(copy)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()?;
+                // written from another thread, a tool that does not read would block it
+                if let Some(mut stdin) = child.stdin.take() {
+                    let s = s.to_string();
+                    thread::spawn(move || stdin.write_all(s.as_bytes()));
+                }
+
+                match wait(&mut child, copy)? {
+                    status if status.success() => Ok(()),
+                    status => Err(io::Error::other(format!("{} exited with {}", copy, status))),
+                }
+            }
+        }
+    }
+
+    // a local program is run, which may take until it times out
+    pub fn can_stall(&self) -> bool {
+        matches!(self, Self::Command { copy, .. } if !copy.is_empty())
+    }
+
+    // only local programs can be read from
+    pub fn can_paste(&self) -> bool {
+        matches!(self, Self::Command { paste, .. } if !paste.is_empty())
+    }
+
+    // none when there is nothing to read or it cannot be read
+    pub fn paste(&self) -> Option<String> {
+        let Self::Command { paste, .. } = self else {
+            return None;
+        };
+        if paste.is_empty() {
+            return None;
+        }
+
+        let mut child = command(paste)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        // read from another thread, a tool blocked on a full pipe would never exit
+        let mut stdout = child.stdout.take()?;
+        let reader = thread::spawn(move || {
+            let mut bytes = Vec::new();
+            stdout.read_to_end(&mut bytes).map(|_| bytes)
+        });
+
+        if !wait(&mut child, paste).ok()?.success() {
+            return None;
+        }
+        let bytes = reader.join().ok()?.ok()?;
+        if bytes.is_empty() {
+            return None;
+        }
+
+        String::from_utf8(bytes).ok()
+    }
+}
+
+// the exit status of `child`, which is killed once it ran for longer than TIMEOUT
+fn wait(child: &mut Child, line: &str) -> io::Result<ExitStatus> {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{} did not finish in time", program(line)),
+            ));
+        }
+
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+// the program and its arguments, split on whitespace
+fn command(line: &str) -> Command {
+    let mut words = line.split_whitespace();
+    let mut command = Command::new(words.next().unwrap_or_default());
+    command.args(words);
+
+    command
+}
+
+fn program(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or_default()
+}
+
+fn on_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|paths| {
+        env::split_paths(&paths).any(|dir| Path::new(&dir).join(program).is_file())
+    })
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clipboard_base64_1() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64("chào\n".as_bytes()), "Y2jDoG8K");
+    }
+
+    #[test]
+    fn test_clipboard_command_1() {
+        let clipboard = Clipboard::Command {
+            copy: String::from("cat"),
+            paste: String::from("echo hi"),
+        };
+        assert!(clipboard.copy("x").is_ok());
+        assert!(clipboard.can_stall());
+        assert!(clipboard.can_paste());
+        assert_eq!(clipboard.paste(), Some(String::from("hi\n")));
+
+        let clipboard = Clipboard::Command {
+            copy: String::from("false"),
+            paste: String::new(),
+        };
+        assert!(clipboard.copy("x").is_err());
+        assert!(!clipboard.can_paste());
+        assert_eq!(clipboard.paste(), None);
+        assert!(!Clipboard::Osc52.can_stall());
+        assert!(!Clipboard::Osc52.can_paste());
+        assert_eq!(Clipboard::Off.paste(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_clipboard_timeout_1() {
+        let clipboard = Clipboard::Command {
+            copy: String::from("sleep 10"),
+            paste: String::from("sleep 10"),
+        };
+        let start = Instant::now();
+
+        let e = clipboard.copy(&"x".repeat(1 << 20)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(clipboard.paste(), None);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,330 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::clipboard::Clipboard;
+use crate::undo::{Boundary, Coalesce};
+use crate::word::WordRules;
+
+// User settings, read from $XDG_CONFIG_HOME/kame/config (~/.config/kame/config by default).
+// One `key = value` per line, `#` starts a comment:
+//
+// # keep the undo history in ~/.cache/kame/undo
+// undo_file = cache
+// undo_bytes = 8m
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub undo_file: UndoFile,
+    // the undo history forgets its oldest changes past this many of them,
+    // or this many bytes of inserted and deleted text
+    pub undo_steps: usize,
+    pub undo_bytes: usize,
+    // when typing or deleting stops being undone in one step
+    pub undo_coalesce: Coalesce,
+    // shared with other programs by kills, copies and yanks
+    pub clipboard: Clipboard,
+    pub words: WordRules,
+    // lines kept between the cursor and the top or bottom of the window
+    pub scroll_margin: usize,
+    // columns scrolled sideways when the cursor leaves the window, 0 to center it
+    pub hscroll_step: usize,
+    // long lines are wrapped at word boundaries instead of scrolled sideways
+    pub wrap: bool,
+    // lines that could not be understood, shown once the editor is up
+    pub warnings: Vec<String>,
+}
+
+// where the undo history is kept between sessions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UndoFile {
+    Off,
+    // `.name.kame-undo` next to the file
+    Sidecar,
+    // $XDG_CACHE_HOME/kame/undo
+    Cache,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            undo_file: UndoFile::Off,
+            undo_steps: 10_000,
+            undo_bytes: 32 << 20,
+            undo_coalesce: Coalesce {
+                idle: Some(Duration::from_secs(1)),
+                boundary: Boundary::Word,
+                on_move: true,
+            },
+            clipboard: Clipboard::Off,
+            words: WordRules::default(),
+            scroll_margin: 2,
+            hscroll_step: 0,
+            wrap: false,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    // a missing config file is not an error, every setting has a default
+    pub fn load() -> Self {
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+        match dir.and_then(|dir| fs::read_to_string(dir.join("kame").join("config")).ok()) {
+            Some(s) => Self::parse(&s),
+            None => Self::default(),
+        }
+    }
+
+    pub fn parse(s: &str) -> Self {
+        let mut config = Self::default();
+
+        for (i, line) in s.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                config
+                    .warnings
+                    .push(format!("config line {}: expected key = value", i + 1));
+                continue;
+            };
+
+            if let Err(e) = config.set(key.trim(), value.trim()) {
+                config
+                    .warnings
+                    .push(format!("config line {}: {}", i + 1, e));
+            }
+        }
+
+        config
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "undo_file" => {
+                self.undo_file = match value {
+                    "off" => UndoFile::Off,
+                    "sidecar" => UndoFile::Sidecar,
+                    "cache" => UndoFile::Cache,
+                    _ => return Err(format!("{} must be off, sidecar or cache", key)),
+                }
+            }
+            "undo_steps" => {
+                self.undo_steps = value
+                    .parse()
+                    .map_err(|_| format!("{} must be a number", key))?
+            }
+            "undo_bytes" => {
+                self.undo_bytes =
+                    parse_size(value).ok_or(format!("{} must be a size, e.g. 512k or 32m", key))?
+            }
+            "undo_idle_ms" => {
+                self.undo_coalesce.idle = match value {
+                    "off" => None,
+                    _ => Some(Duration::from_millis(value.parse().map_err(|_| {
+                        format!("{} must be a number of milliseconds or off", key)
+                    })?)),
+                }
+            }
+            "undo_boundary" => {
+                self.undo_coalesce.boundary = Boundary::from_name(value)
+                    .ok_or(format!("{} must be off, line or word", key))?
+            }
+            "undo_break_on_move" => {
+                self.undo_coalesce.on_move = match value {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(format!("{} must be true or false", key)),
+                }
+            }
+            "clipboard" => {
+                self.clipboard = match value {
+                    "off" => Clipboard::Off,
+                    "auto" => Clipboard::detect(),
+                    "osc52" => Clipboard::Osc52,
+                    _ => return Err(format!("{} must be off, auto or osc52", key)),
+                }
+            }
+            "clipboard_copy" | "clipboard_paste" => {
+                let (mut copy, mut paste) = match &self.clipboard {
+                    Clipboard::Command { copy, paste } => (copy.clone(), paste.clone()),
+                    _ => (String::new(), String::new()),
+                };
+                if key == "clipboard_copy" {
+                    copy = value.to_string();
+                } else {
+                    paste = value.to_string();
+                }
+                self.clipboard = Clipboard::Command { copy, paste };
+            }
+            "word_chars" => self.words.extra = value.to_string(),
+            "subwords" => {
+                self.words.subwords = match value {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(format!("{} must be true or false", key)),
+                }
+            }
+            "scroll_margin" => {
+                self.scroll_margin = value
+                    .parse()
+                    .map_err(|_| format!("{} must be a number", key))?
+            }
+            "hscroll_step" => {
+                self.hscroll_step = value
+                    .parse()
+                    .map_err(|_| format!("{} must be a number", key))?
+            }
+            "wrap" => {
+                self.wrap = match value {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(format!("{} must be true or false", key)),
+                }
+            }
+            _ => return Err(format!("unknown setting {}", key)),
+        }
+
+        Ok(())
+    }
+}
+
+// a comment starts with a '#' at the start of the line or after a space, so that
+// values like `word_chars = _#` can hold one
+fn strip_comment(line: &str) -> &str {
+    let mut prev = None;
+    for (i, c) in line.char_indices() {
+        if c == '#' && prev.is_none_or(char::is_whitespace) {
+            return &line[..i];
+        }
+        prev = Some(c);
+    }
+
+    line
+}
+
+// a number of bytes, with an optional k, m or g suffix for powers of 1024
+fn parse_size(s: &str) -> Option<usize> {
+    let s = s.to_lowercase();
+    let (n, shift) = match s.strip_suffix(['k', 'm', 'g']) {
+        Some(n) => (n, 10 * (" kmg".find(s.chars().last()?)? as u32)),
+        None => (s.as_str(), 0),
+    };
+
+    n.trim().parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_parse_1() {
+        let config = Config::parse("# undo\n  undo_file = sidecar  # next to the file\n\n");
+        assert_eq!(config.undo_file, UndoFile::Sidecar);
+        assert!(config.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_config_parse_2() {
+        let config = Config::parse("undo_file = disk\nfoo = 1\nundo_file\nundo_file=cache");
+        assert_eq!(config.undo_file, UndoFile::Cache);
+        assert_eq!(
+            config.warnings,
+            vec![
+                "config line 1: undo_file must be off, sidecar or cache",
+                "config line 2: unknown setting foo",
+                "config line 3: expected key = value",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_parse_3() {
+        let config = Config::parse("undo_steps = 500\nundo_bytes = 2M\nundo_steps = -1");
+        assert_eq!(config.undo_steps, 500);
+        assert_eq!(config.undo_bytes, 2 << 20);
+        assert_eq!(
+            config.warnings,
+            vec!["config line 3: undo_steps must be a number"]
+        );
+
+        assert_eq!(parse_size("100"), Some(100));
+        assert_eq!(parse_size("4 k"), Some(4096));
+        assert_eq!(parse_size("1g"), Some(1 << 30));
+        assert_eq!(parse_size("1t"), None);
+        assert_eq!(parse_size("k"), None);
+    }
+
+    #[test]
+    fn test_config_parse_4() {
+        let config = Config::parse(
+            "undo_idle_ms = 250\nundo_boundary = line\nundo_break_on_move = false\nundo_boundary = char",
+        );
+        assert_eq!(
+            config.undo_coalesce,
+            Coalesce {
+                idle: Some(Duration::from_millis(250)),
+                boundary: Boundary::Line,
+                on_move: false,
+            }
+        );
+        assert_eq!(
+            config.warnings,
+            vec!["config line 4: undo_boundary must be off, line or word"]
+        );
+        assert_eq!(Config::parse("undo_idle_ms = off").undo_coalesce.idle, None);
+    }
+
+    #[test]
+    fn test_config_parse_5() {
+        let config = Config::parse("clipboard_paste = xsel -ob\nclipboard_copy = xsel -ib");
+        assert_eq!(
+            config.clipboard,
+            Clipboard::Command {
+                copy: String::from("xsel -ib"),
+                paste: String::from("xsel -ob"),
+            }
+        );
+        assert_eq!(
+            Config::parse("clipboard = osc52").clipboard,
+            Clipboard::Osc52
+        );
+    }
+
+    #[test]
+    fn test_config_parse_6() {
+        let config = Config::parse("word_chars = _-$\nsubwords = true");
+        assert_eq!(config.words.extra, "_-$");
+        assert!(config.words.subwords);
+        assert_eq!(Config::parse("word_chars =").words.extra, "");
+        assert_eq!(
+            Config::parse("word_chars = _# # and the hash").words.extra,
+            "_#"
+        );
+
+        assert_eq!(strip_comment("#word_chars = _"), "");
+        assert_eq!(
+            strip_comment("word_chars = _# # comment"),
+            "word_chars = _# "
+        );
+        assert_eq!(strip_comment("word_chars = #"), "word_chars = ");
+    }
+
+    #[test]
+    fn test_config_parse_7() {
+        assert_eq!(Config::parse("scroll_margin = 5").scroll_margin, 5);
+        assert_eq!(Config::parse("hscroll_step = 8").hscroll_step, 8);
+        assert!(Config::parse("wrap = true").wrap);
+        assert_eq!(
+            Config::parse("scroll_margin = two").warnings,
+            vec!["config line 1: scroll_margin must be a number"]
+        );
+    }
+}
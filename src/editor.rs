@@ -1,49 +1,298 @@
+use std::cell::RefCell;
 use std::fs::{self, File, OpenOptions};
-use std::io::Write;
-
-use crate::buffer::Buffer;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{self, Path, PathBuf};
+use std::rc::Rc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::config::Config;
+use crate::encoding::{ENCODINGS, Encoding};
+use crate::kill_ring::KillRing;
+use crate::line_ending::{LINE_ENDINGS, LineEnding};
+use crate::mark::{Gravity, MarkId};
 use crate::message::Message;
-use crate::representer::{Element, Representer};
-use crate::undo::{Command, UndoManager};
+use crate::representer::{Element, ElementType, Representer};
+use crate::snapshot::Snapshot;
+use crate::storage::{self, TextStorage};
+use crate::undo::{Command, TreeRow, UndoManager};
+use crate::undo_file;
+use crate::width;
+use crate::wrap;
 
 const NEWLINE: u8 = b'\n';
 const DIRTY_MASK: i8 = 0x01;
 const SAVED_MASK: i8 = 0x02;
 const MANUAL_POPUP_MASK: i8 = 0x04;
 const PROMPT_MASK: i8 = 0x08;
+// bytes read around the cursor at first by the word commands
+const WORD_WINDOW_LEN: usize = 256;
+
+// position of the insertion point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub line: usize,
+    // bytes from the beginning of the line
+    pub offset: usize,
+    // chars from the beginning of the line
+    pub index: usize,
+    // terminal cells from the beginning of the line
+    pub col: usize,
+}
+
+// a screen row: a line and which of its rows, lines are a single row when not wrapped
+type Row = (usize, usize);
+
+// what the minibuffer is asking for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Query {
+    Encoding,
+    LineEnding,
+    UndoToTime,
+    GotoLine,
+    SwitchBuffer,
+    KillBuffer,
+    // the buffer at this index is modified, kill it anyway?
+    ConfirmKill(usize),
+}
+
+// commands that act differently right after themselves
+#[derive(Debug, Clone, Default, PartialEq)]
+enum Chain {
+    #[default]
+    None,
+    // a kill adds to the same kill ring entry
+    Kill,
+    // the text pasted by a yank, replaced by a yank-pop
+    Yank(Range<usize>),
+    // recenter-top-bottom pressed this many times before, in a row
+    Recenter(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Case {
+    Upper,
+    Lower,
+    // the first letter of each word upper, the rest lower
+    Capital,
+}
+
+#[derive(Debug)]
+struct Minibuffer {
+    query: Query,
+    input: String,
+}
+
+// one line of the buffer list
+#[derive(Debug, Clone, PartialEq)]
+pub struct BufferRow {
+    pub name: String,
+    // bytes of text
+    pub size: usize,
+    pub modified: bool,
+    pub path: String,
+    pub selected: bool,
+}
+
+// an open buffer that is not shown, it trades places with the editor's own fields when
+// it is switched to
+#[derive(Debug)]
+struct Document {
+    path: String,
+    encoding: Encoding,
+    line_ending: LineEnding,
+    flags: i8,
+    buffer: Box<dyn TextStorage>,
+    isearch: ISearch,
+    undo_manager: UndoManager,
+    mark: Option<MarkId>,
+    mark_active: bool,
+    snapshot: Option<Snapshot>,
+    save_job: Option<JoinHandle<io::Result<u64>>>,
+    disk_hash: Option<u64>,
+    top: usize,
+    top_row: usize,
+    left: usize,
+}
+
+impl Document {
+    // the raw contents of a file, in any supported encoding and line ending
+    // also tells whether the file mixes line endings
+    fn open(bytes: &[u8], path: String, config: &Config) -> (Self, bool) {
+        let encoding = Encoding::detect(bytes);
+        let text = encoding.decode(bytes);
+        let (line_ending, mixed) = LineEnding::detect(&text);
+        let disk_hash = undo_file::hash(bytes);
+        let buffer = storage::init(LineEnding::normalize(text));
+
+        let mut undo_manager = undo_file::load(&path, config.undo_file, disk_hash, buffer.len())
+            .unwrap_or_else(UndoManager::new);
+        undo_manager.limit(config.undo_steps, config.undo_bytes);
+        undo_manager.coalesce(config.undo_coalesce);
+
+        let doc = Self {
+            path,
+            encoding,
+            line_ending,
+            flags: 0,
+            buffer,
+            isearch: ISearch::new(),
+            undo_manager,
+            mark: None,
+            mark_active: false,
+            snapshot: None,
+            save_job: None,
+            disk_hash: Some(disk_hash),
+            top: 0,
+            top_row: 0,
+            left: 0,
+        };
+
+        (doc, mixed)
+    }
+
+    // see `Editor::is_modified`
+    fn is_modified(&self) -> bool {
+        is_modified(self.flags, &self.undo_manager)
+    }
+}
 
 #[derive(Debug)]
 pub struct Editor {
     pub path: String,
+    // encoding of the file on disk
+    pub encoding: Encoding,
+    // line breaks of the file on disk
+    pub line_ending: LineEnding,
+    pub config: Config,
 
     flags: i8,
-    buffer: Buffer,
+    buffer: Box<dyn TextStorage>,
     isearch: ISearch,
+    minibuffer: Option<Minibuffer>,
+    // feedback shown in the prompt line until the next key press
+    echo: Option<String>,
     undo_manager: UndoManager,
+    // state to go back to when the undo tree browser is cancelled, none when it is closed
+    undo_browser: Option<usize>,
+    // the row selected in the buffer list, none when it is closed
+    buffer_browser: Option<usize>,
+    // the other open buffers, the most recently shown first
+    documents: Vec<Document>,
+    kill_ring: KillRing,
+    // the system clipboard being read for a yank, which is done at the mark once it is read
+    paste_job: Option<(MarkId, JoinHandle<Option<String>>)>,
+    // the newest kill being written to the system clipboard
+    copy_job: Option<JoinHandle<io::Result<()>>>,
+    // a kill came while `copy_job` was running, it is written once that one is done
+    copy_pending: bool,
+    // the other end of the region, the cursor being the first one
+    mark: Option<MarkId>,
+    // the region is highlighted, until the text changes
+    mark_active: bool,
+    // left by the previous command, and by the one being run
+    chain: Chain,
+    next_chain: Chain,
+    // the last snapshot taken, reused until the text changes
+    snapshot: Option<Snapshot>,
+    // the save being written in the background, it gives the hash of what it wrote
+    save_job: Option<JoinHandle<io::Result<u64>>>,
+    // hash of the file as it is on disk, none if a save failed half way
+    disk_hash: Option<u64>,
     top: usize,
+    // the row of the top line the window starts with, when it is wrapped
+    top_row: usize,
+    // first display column of the window, when the cursor is past its right edge
+    left: usize,
+    // size of the main area at the last render, what paging moves by and lines wrap at
+    width: usize,
+    height: usize,
+    // long lines are wrapped to the window instead of being scrolled sideways
+    wrap: bool,
+    // where the rows of the lines start, while wrapping
+    rows: RefCell<wrap::RowCache>,
+    // the cursor jumped somewhere else (undo, redo), center it if it is off-screen
+    recenter: bool,
     _log: File, // TODO: Remove
 }
 
 impl Editor {
     pub fn new(buffer: String, path: String) -> Self {
-        let buffer = Buffer::init(buffer);
+        Self::with_storage(storage::init(buffer), path)
+    }
+
+    // the raw contents of a file, in any supported encoding and line ending
+    pub fn open(bytes: &[u8], path: String, config: Config) -> Self {
+        let (mut doc, mixed) = Document::open(bytes, path, &config);
+        let line_ending = doc.line_ending;
+
+        // starts out empty and trades places with the file
+        let mut editor = Self::new(String::new(), String::new());
+        editor.swap_document(&mut doc);
+
+        editor.echo = if mixed {
+            Some(format!(
+                "mixed line endings, they will be saved as {}",
+                line_ending.name()
+            ))
+        } else {
+            config.warnings.first().cloned()
+        };
+        editor.wrap = config.wrap;
+        editor.config = config;
+
+        editor
+    }
+
+    pub fn with_storage(buffer: Box<dyn TextStorage>, path: String) -> Self {
         let log = OpenOptions::new()
-            .write(true)
             .create(true)
             .append(true)
             .open("tmp/log.log")
             .unwrap();
         let isearch = ISearch::new();
-        let undo_manager = UndoManager::new();
+        let config = Config::default();
+        let mut undo_manager = UndoManager::new();
+        undo_manager.limit(config.undo_steps, config.undo_bytes);
+        undo_manager.coalesce(config.undo_coalesce);
         let flags = 0;
 
         Self {
             path,
+            encoding: Encoding::Utf8,
+            line_ending: LineEnding::Lf,
+            config,
             flags,
             buffer,
             isearch,
+            minibuffer: None,
+            echo: None,
             undo_manager,
+            undo_browser: None,
+            buffer_browser: None,
+            documents: Vec::new(),
+            kill_ring: KillRing::new(),
+            paste_job: None,
+            copy_job: None,
+            copy_pending: false,
+            mark: None,
+            mark_active: false,
+            chain: Chain::None,
+            next_chain: Chain::None,
+            snapshot: None,
+            save_job: None,
+            disk_hash: None,
             top: 0,
+            top_row: 0,
+            left: 0,
+            width: 0,
+            height: 0,
+            wrap: false,
+            rows: RefCell::default(),
+            recenter: false,
             _log: log,
         }
     }
@@ -54,12 +303,28 @@ impl Editor {
         }
 
         self.flags &= !SAVED_MASK;
-
-        if self.is_prompted() {
+        self.echo = None;
+        let cursor = self.buffer.cursor();
+        let version = self.buffer.version();
+
+        if self.minibuffer.is_some() {
+            self.match_minibuffer(message);
+        } else if self.undo_browser.is_some() {
+            self.match_undo_browser(message);
+        } else if self.buffer_browser.is_some() {
+            self.match_buffer_browser(message);
+        } else if self.is_prompted() {
             self.match_isearch_buffer(message);
         } else {
             self.match_editing_buffer(message);
         }
+
+        if self.buffer.version() != version {
+            self.mark_active = false;
+        } else if self.buffer.cursor() != cursor {
+            self.undo_manager.moved();
+        }
+        self.chain = std::mem::take(&mut self.next_chain);
     }
 
     fn match_editing_buffer(&mut self, message: Message) {
@@ -67,20 +332,55 @@ impl Editor {
             // ---------------- Editing ---------------------------------------- //
             Message::InsertNewLine => self.insert_newline(),
             Message::Insert(c) => self.insert_char(c),
+            Message::Paste(s) => self.paste(&s),
             Message::InsertTab => self.insert_tab(),
             Message::DeleteUnderCursor => self.delete_under_cursor(),
             Message::DeleteBeforeCursor => self.delete_before_cursor(),
+            Message::DeleteCodePointBeforeCursor => self.delete_code_point_before_cursor(),
             Message::CutToEndOfLine => self.cut_to_eol(),
+            Message::KillWord => self.kill_word(true),
+            Message::BackwardKillWord => self.kill_word(false),
+            Message::TransposeWords => self.transpose_words(),
+            Message::UpcaseWord => self.change_case(Case::Upper),
+            Message::DowncaseWord => self.change_case(Case::Lower),
+            Message::CapitalizeWord => self.change_case(Case::Capital),
+            Message::Yank => self.yank(),
+            Message::YankPop => self.yank_pop(),
+            Message::SetMark => self.set_mark(),
+            Message::KillRegion => self.kill_region(),
+            Message::CopyRegion => self.copy_region(),
             Message::Undo => self.undo(),
             Message::Redo => self.redo(),
+            Message::NextBranch => self.switch_branch(true),
+            Message::PreviousBranch => self.switch_branch(false),
+            Message::UndoToTime => self.read_minibuffer(Query::UndoToTime),
+            Message::UndoTree => self.undo_browser = Some(self.undo_manager.current()),
 
             // ---------------- Movement --------------------------------------- //
             Message::ForwardOneChar => self.forward_one_char(),
             Message::BackwardOneChar => self.backward_one_char(),
+            Message::ForwardWord => {
+                let i = self.word_end(self.buffer.cursor());
+                self.buffer.jump(i);
+            }
+            Message::BackwardWord => {
+                let i = self.word_start(self.buffer.cursor());
+                self.buffer.jump(i);
+            }
             Message::JumpToBeginningOfLine => self.jump_to_bol(),
             Message::JumpToEndOfLine => self.jump_to_eol(),
             Message::JumpToNextLine => self.jump_to_next_line(),
             Message::JumpToPreviousLine => self.jump_to_previous_line(),
+            Message::NextPage => self.scroll_page(true),
+            Message::PreviousPage => self.scroll_page(false),
+            Message::Recenter => self.recenter(),
+            Message::BeginningOfBuffer => self.jump_far(0),
+            Message::EndOfBuffer => self.jump_far(self.buffer.len()),
+            Message::GotoLine => self.read_minibuffer(Query::GotoLine),
+            Message::ToggleWrap => self.toggle_wrap(),
+            Message::SwitchBuffer => self.read_minibuffer(Query::SwitchBuffer),
+            Message::KillBuffer => self.read_minibuffer(Query::KillBuffer),
+            Message::BufferList => self.buffer_browser = Some(0),
 
             // ---------------- File operation --------------------------------- //
             Message::Noop => {}
@@ -88,13 +388,355 @@ impl Editor {
             Message::Save => self.save(),
             Message::UserManual => self.toggle_popup(),
             Message::Search => self.toggle_prompt(),
+            Message::SetEncoding => self.read_minibuffer(Query::Encoding),
+            Message::SetLineEnding => self.read_minibuffer(Query::LineEnding),
+        }
+    }
+
+    fn match_minibuffer(&mut self, message: Message) {
+        let Some(minibuffer) = self.minibuffer.as_mut() else {
+            return;
+        };
+
+        match message {
+            Message::Insert(c) => minibuffer.input.push(c),
+            Message::Paste(s) => minibuffer.input.push_str(s.lines().next().unwrap_or("")),
+            Message::DeleteBeforeCursor => {
+                minibuffer.input.pop();
+            }
+            Message::InsertTab => self.complete_minibuffer(),
+            Message::InsertNewLine => {
+                if let Some(minibuffer) = self.minibuffer.take() {
+                    self.submit_minibuffer(minibuffer);
+                }
+            }
+            Message::Noop => {}
+            Message::Quit => panic!("something wrong"),
+            _ => self.minibuffer = None,
+        }
+    }
+
+    fn read_minibuffer(&mut self, query: Query) {
+        self.minibuffer = Some(Minibuffer {
+            query,
+            input: String::new(),
+        });
+    }
+
+    fn submit_minibuffer(&mut self, minibuffer: Minibuffer) {
+        match minibuffer.query {
+            Query::Encoding => self.set_encoding(&minibuffer.input),
+            Query::LineEnding => self.set_line_ending(&minibuffer.input),
+            Query::UndoToTime => self.undo_to_time(&minibuffer.input),
+            Query::GotoLine => self.goto_line(&minibuffer.input),
+            Query::SwitchBuffer => match self.find_buffer(&minibuffer.input, 1) {
+                Some(0) => {}
+                Some(i) => self.switch_buffer(i),
+                None => self.echo = Some(format!("no buffer named {}", minibuffer.input)),
+            },
+            Query::KillBuffer => match self.find_buffer(&minibuffer.input, 0) {
+                Some(i) if self.buffer_rows()[i].modified => {
+                    self.read_minibuffer(Query::ConfirmKill(i))
+                }
+                Some(i) => self.kill_buffer(i),
+                None => self.echo = Some(format!("no buffer named {}", minibuffer.input)),
+            },
+            Query::ConfirmKill(i) => match minibuffer.input.trim() {
+                "yes" | "y" => self.kill_buffer(i),
+                _ => self.echo = Some(String::from("buffer not killed")),
+            },
+        }
+    }
+
+    // what the input can be completed to
+    fn candidates(&self, query: Query) -> Vec<String> {
+        match query {
+            Query::Encoding => ENCODINGS.map(|e| e.name().to_string()).to_vec(),
+            Query::LineEnding => LINE_ENDINGS.map(|e| e.name().to_string()).to_vec(),
+            Query::SwitchBuffer | Query::KillBuffer => self.buffer_names(),
+            _ => Vec::new(),
+        }
+    }
+
+    // extends the input as far as the candidates it starts agree
+    fn complete_minibuffer(&mut self) {
+        let Some(minibuffer) = self.minibuffer.as_ref() else {
+            return;
+        };
+
+        let matches = self
+            .candidates(minibuffer.query)
+            .into_iter()
+            .filter(|c| c.starts_with(&minibuffer.input))
+            .collect::<Vec<String>>();
+        let Some(first) = matches.first() else {
+            return;
+        };
+        let common = matches.iter().fold(first.as_str(), |common, c| {
+            let n = common
+                .char_indices()
+                .zip(c.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(common.len().min(c.len()), |((i, _), _)| i);
+            &common[..n]
+        });
+
+        let common = common.to_string();
+        if let Some(minibuffer) = self.minibuffer.as_mut() {
+            minibuffer.input = common;
+        }
+    }
+
+    // the candidates the input could still become, when there is more than one
+    pub fn minibuffer_completions(&self) -> Option<String> {
+        let minibuffer = self.minibuffer.as_ref()?;
+        let matches = self
+            .candidates(minibuffer.query)
+            .into_iter()
+            .filter(|c| c.starts_with(&minibuffer.input))
+            .collect::<Vec<String>>();
+        if minibuffer.input.is_empty() || matches.len() < 2 {
+            return None;
+        }
+
+        Some(format!("{{{}}}", matches.join(" | ")))
+    }
+
+    pub fn minibuffer_prompt(&self) -> Option<String> {
+        let minibuffer = self.minibuffer.as_ref()?;
+        let label = match minibuffer.query {
+            Query::Encoding => {
+                let names = ENCODINGS.map(|e| e.name()).join(", ");
+                format!("encoding ({})", names)
+            }
+            Query::LineEnding => {
+                let names = LINE_ENDINGS.map(|e| e.name()).join(", ");
+                format!("line ending ({})", names)
+            }
+            Query::UndoToTime => String::from("go back to (e.g. 30s, 5m, 2h ago)"),
+            Query::GotoLine => format!("goto line (1-{})", self.buffer.line_count()),
+            Query::SwitchBuffer => match self.buffer_names().get(1) {
+                Some(name) => format!("switch to buffer (default {})", name),
+                None => String::from("switch to buffer"),
+            },
+            Query::KillBuffer => format!("kill buffer (default {})", self.buffer_names()[0]),
+            Query::ConfirmKill(i) => format!(
+                "{} is modified, kill anyway? (yes or no)",
+                self.buffer_names()[i]
+            ),
+        };
+
+        Some(format!(" {}: {}", label, minibuffer.input))
+    }
+
+    pub fn echo(&self) -> Option<&str> {
+        self.echo.as_deref()
+    }
+
+    // moving around the tree previews the text at the selected state right in the buffer
+    fn match_undo_browser(&mut self, message: Message) {
+        match message {
+            Message::JumpToPreviousLine | Message::Undo => self.undo(),
+            Message::JumpToNextLine | Message::Redo => self.redo(),
+            Message::ForwardOneChar => self.switch_branch(true),
+            Message::BackwardOneChar => self.switch_branch(false),
+            Message::InsertNewLine | Message::UndoTree => self.undo_browser = None,
+            Message::Noop => {}
+            Message::Quit => panic!("something wrong"),
+            _ => {
+                if let Some(id) = self.undo_browser.take() {
+                    self.jump_to_state(id);
+                }
+            }
+        }
+    }
+
+    // the rows of the undo tree browser, none when it is closed
+    pub fn undo_tree(&self) -> Option<Vec<TreeRow>> {
+        self.undo_browser?;
+
+        Some(self.undo_manager.rows())
+    }
+
+    fn match_buffer_browser(&mut self, message: Message) {
+        let Some(selected) = self.buffer_browser else {
+            return;
+        };
+
+        match message {
+            Message::JumpToPreviousLine => self.buffer_browser = Some(selected.saturating_sub(1)),
+            Message::JumpToNextLine => {
+                self.buffer_browser = Some((selected + 1).min(self.documents.len()));
+            }
+            Message::InsertNewLine => {
+                self.buffer_browser = None;
+                if selected > 0 {
+                    self.switch_buffer(selected);
+                }
+            }
+            Message::Noop => {}
+            Message::Quit => panic!("something wrong"),
+            _ => self.buffer_browser = None,
+        }
+    }
+
+    // the rows of the buffer list, none when it is closed
+    pub fn buffer_list(&self) -> Option<Vec<BufferRow>> {
+        let selected = self.buffer_browser?;
+        let mut rows = self.buffer_rows();
+        rows[selected].selected = true;
+
+        Some(rows)
+    }
+
+    // the shown buffer first, then the others from the most recently shown one
+    fn buffer_rows(&self) -> Vec<BufferRow> {
+        let shown = BufferRow {
+            name: String::new(),
+            size: self.buffer.len(),
+            modified: self.is_modified(),
+            path: self.path.clone(),
+            selected: false,
+        };
+        let others = self.documents.iter().map(|doc| BufferRow {
+            name: String::new(),
+            size: doc.buffer.len(),
+            modified: doc.is_modified(),
+            path: doc.path.clone(),
+            selected: false,
+        });
+
+        let mut rows = std::iter::once(shown)
+            .chain(others)
+            .collect::<Vec<BufferRow>>();
+        for (row, name) in rows.iter_mut().zip(self.buffer_names()) {
+            row.name = name;
+        }
+
+        rows
+    }
+
+    // file names, followed by their directory for the ones that are open more than once
+    fn buffer_names(&self) -> Vec<String> {
+        let paths = std::iter::once(&self.path)
+            .chain(self.documents.iter().map(|doc| &doc.path))
+            .map(Path::new)
+            .collect::<Vec<&Path>>();
+        let file_name = |path: &Path| {
+            path.file_name()
+                .map_or(path.to_string_lossy(), |name| name.to_string_lossy())
+                .into_owned()
+        };
+
+        paths
+            .iter()
+            .map(|path| {
+                let name = file_name(path);
+                if paths.iter().filter(|p| file_name(p) == name).count() == 1 {
+                    return name;
+                }
+
+                let dir = path.parent().map(file_name).unwrap_or_default();
+                format!("{}<{}>", name, dir)
+            })
+            .collect()
+    }
+
+    // the buffer called `name`, or the only one whose name starts with it
+    // the buffer at `default` when `name` is empty
+    fn find_buffer(&self, name: &str, default: usize) -> Option<usize> {
+        let names = self.buffer_names();
+        if name.is_empty() {
+            return (default < names.len()).then_some(default);
+        }
+        if let Some(i) = names.iter().position(|n| n == name) {
+            return Some(i);
+        }
+
+        let mut matches = names
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.starts_with(name));
+        match (matches.next(), matches.next()) {
+            (Some((i, _)), None) => Some(i),
+            _ => None,
+        }
+    }
+
+    // opens another file without showing it, unless it is open already under any path
+    pub fn open_buffer(&mut self, bytes: &[u8], path: String) {
+        let file = canonical(&path);
+        let mut paths =
+            std::iter::once(&self.path).chain(self.documents.iter().map(|doc| &doc.path));
+        if paths.any(|p| canonical(p) == file) {
+            return;
+        }
+
+        let (doc, _) = Document::open(bytes, path, &self.config);
+        self.documents.push(doc);
+    }
+
+    // shows the buffer at `i` in `buffer_rows`
+    fn switch_buffer(&mut self, i: usize) {
+        self.finish_jobs();
+        self.undo_manager.boundary();
+
+        let mut doc = self.documents.remove(i - 1);
+        self.swap_document(&mut doc);
+        self.documents.insert(0, doc);
+    }
+
+    // the shown buffer and `doc` trade places
+    fn swap_document(&mut self, doc: &mut Document) {
+        // a pending yank goes to the buffer it was asked for in
+        self.finish_paste(false);
+        std::mem::swap(&mut self.path, &mut doc.path);
+        std::mem::swap(&mut self.encoding, &mut doc.encoding);
+        std::mem::swap(&mut self.line_ending, &mut doc.line_ending);
+        std::mem::swap(&mut self.flags, &mut doc.flags);
+        std::mem::swap(&mut self.buffer, &mut doc.buffer);
+        std::mem::swap(&mut self.isearch, &mut doc.isearch);
+        std::mem::swap(&mut self.undo_manager, &mut doc.undo_manager);
+        std::mem::swap(&mut self.mark, &mut doc.mark);
+        std::mem::swap(&mut self.mark_active, &mut doc.mark_active);
+        std::mem::swap(&mut self.snapshot, &mut doc.snapshot);
+        std::mem::swap(&mut self.save_job, &mut doc.save_job);
+        std::mem::swap(&mut self.disk_hash, &mut doc.disk_hash);
+        std::mem::swap(&mut self.top, &mut doc.top);
+        std::mem::swap(&mut self.top_row, &mut doc.top_row);
+        std::mem::swap(&mut self.left, &mut doc.left);
+        self.rows.get_mut().clear();
+        self.flags &= !SAVED_MASK;
+    }
+
+    // the buffer at `i` in `buffer_rows` is closed, its undo history stored
+    fn kill_buffer(&mut self, i: usize) {
+        if self.documents.is_empty() {
+            self.echo = Some(String::from("cannot kill the only buffer"));
+            return;
+        }
+
+        let name = self.buffer_names().remove(i);
+        if i == 0 {
+            self.close_buffer();
+            let mut doc = self.documents.remove(0);
+            self.swap_document(&mut doc);
+        } else {
+            let mut doc = self.documents.remove(i - 1);
+            self.swap_document(&mut doc);
+            self.close_buffer();
+            self.swap_document(&mut doc);
+        }
+
+        if self.echo.is_none() {
+            self.echo = Some(format!("killed {}", name));
         }
     }
 
     fn match_isearch_buffer(&mut self, message: Message) {
         match message {
             Message::InsertNewLine => {
-                if let Some(id) = self.isearch.fetch_next() {
+                if let Some(id) = self.isearch.fetch_next(self.buffer.as_ref()) {
                     self.buffer.jump(id);
                 }
             }
@@ -118,8 +760,10 @@ impl Editor {
         self.flags & MANUAL_POPUP_MASK == MANUAL_POPUP_MASK
     }
 
+    // the text differs from the last save, or something that is not part of the undo
+    // history (encoding, line ending, a failed save) did
     pub fn is_modified(&self) -> bool {
-        self.flags & DIRTY_MASK == DIRTY_MASK
+        is_modified(self.flags, &self.undo_manager)
     }
 
     pub fn is_saved(&self) -> bool {
@@ -133,98 +777,191 @@ impl Editor {
     fn toggle_prompt(&mut self) {
         self.flags ^= PROMPT_MASK;
 
-        self.isearch.clear();
+        self.isearch.clear(self.buffer.as_mut());
+        if self.is_prompted()
+            && let Some(region) = self.region()
+            && !region.is_empty()
+        {
+            self.isearch.scope = Some((
+                self.buffer.marks_mut().create(region.start, Gravity::Right),
+                self.buffer.marks_mut().create(region.end, Gravity::Left),
+            ));
+            self.mark_active = false;
+        }
     }
 
-    // TODO: Optimize & unit tests
-    pub fn get_current_point(&self) -> (usize, usize) {
-        let mut rows = 0;
-        let mut cols = 0;
-        for b in self.buffer.before_insertion_point().iter() {
-            if *b == NEWLINE {
-                rows += 1;
-                cols = 0;
-            } else {
-                cols += 1;
-            }
-        }
+    // the search only looks between the ends the region had when it started
+    pub fn is_search_in_region(&self) -> bool {
+        self.isearch.scope.is_some()
+    }
+
+    pub fn get_current_point(&self) -> Point {
+        let i = self.buffer.cursor();
+        let (offset, line) = self.buffer.point(i);
+        let (_, prefix) = self.buffer.str_between(i - offset, i);
 
-        (cols, rows)
+        Point {
+            line,
+            offset,
+            index: prefix.chars().count(),
+            col: width::advance(&prefix, 0),
+        }
     }
 
-    pub fn make_cursor_visible(&mut self, point: (usize, usize), height: usize) -> (u16, u16) {
+    // scrolls by half a window once the cursor gets within the scroll margin of an edge,
+    // the margin is kept where there are rows beyond it
+    pub fn make_cursor_visible(&mut self, point: Point, width: usize, height: usize) -> (u16, u16) {
+        self.width = width;
+        self.height = height;
         let adjust_window = height / 2;
+        let margin = self.scroll_margin(height);
+        let (cursor, col) = self.cursor_row(point);
+        self.top_row = self.top_row.min(self.row_starts(self.top).len() - 1);
+        let top = (self.top, self.top_row);
+        let (bottom, _) = self.rows_forward(top, height.saturating_sub(1));
+        let (below, below_n) = self.rows_forward(cursor, margin);
+        let off_screen = cursor < top || cursor > bottom;
+
+        let top = if std::mem::take(&mut self.recenter) && off_screen {
+            self.rows_back(cursor, adjust_window)
+        } else if below > bottom {
+            self.rows_forward(top, adjust_window).0
+        } else if cursor < self.rows_forward(top, margin).0 {
+            self.rows_back(top, adjust_window)
+        } else {
+            top
+        };
+        // however far the cursor went, it ends up inside the margins
+        let top = top
+            .max(self.rows_back(cursor, height.saturating_sub(1 + below_n)))
+            .min(self.rows_back(cursor, margin));
+        (self.top, self.top_row) = top;
+
+        let col = if self.wrap {
+            col.min(width.saturating_sub(1))
+        } else {
+            self.make_column_visible(col, width)
+        };
+
+        (col as u16, self.rows_between(top, cursor) as u16)
+    }
+
+    // scrolls sideways when the cursor is out of the window or on a column taken by a
+    // truncation indicator: by `hscroll_step` columns, or so that the cursor is in the
+    // middle when that is not enough or the step is 0
+    fn make_column_visible(&mut self, col: usize, width: usize) -> usize {
+        let visible = |left: usize| (left == 0 || col > left) && col + 1 < left + width;
 
-        if point.1 >= self.top + height {
-            self.top += adjust_window;
-            self.top = self.top.max(point.1);
-        } else if point.1 < self.top {
-            self.top = self.top.saturating_sub(adjust_window).min(point.1);
+        if !visible(self.left) {
+            let step = self.config.hscroll_step;
+            let stepped = if col > self.left {
+                self.left + step
+            } else {
+                self.left.saturating_sub(step)
+            };
+
+            self.left = if step > 0 && visible(stepped) {
+                stepped
+            } else {
+                col.saturating_sub(width / 2)
+            };
         }
 
-        (point.0 as u16, (point.1.saturating_sub(self.top)) as u16)
+        col - self.left
+    }
+
+    // lines kept between the cursor and the top or bottom of a window this high
+    fn scroll_margin(&self, height: usize) -> usize {
+        self.config.scroll_margin.min(height.saturating_sub(1) / 2)
     }
 
-    // display from top-th line until (top + height)-th line
+    // display from top-th line until (top + height)-th line, from left-th column on
     // TODO: Rework this shit
-    pub fn viewable_contents(&mut self, height: usize) -> Representer {
+    pub fn viewable_contents(&mut self, width: usize, height: usize) -> Representer {
         assert!(height > 0, "invalid height");
 
-        let top = self.top;
-        let mut rows_cnt = 0;
+        let mut representer =
+            Representer::new(self.left, if self.wrap { usize::MAX } else { width });
+        if self.buffer.line_start(self.top).is_none() {
+            return representer;
+        }
+        let top = (self.top, self.top_row);
+        let start = self.row_offset(top);
+        let end = match self.rows_forward(top, height) {
+            (row, n) if n == height => self.row_offset(row),
+            _ => self.buffer.len(),
+        };
+        // where rows of wrapped lines start, they end with a newline of their own
+        let breaks = match self.wrap {
+            true => (top.0..=self.buffer.point(end).1)
+                .flat_map(|line| {
+                    let offset = self.buffer.line_start(line).unwrap_or(0);
+                    let starts = self.row_starts(line);
+                    (1..starts.len()).map(move |r| offset + starts[r])
+                })
+                .filter(|i| start < *i && *i < end)
+                .collect(),
+            false => Vec::new(),
+        };
+
+        let matches = self.isearch.ranges(self.buffer.as_ref());
+        let region = self.region().unwrap_or(0..0);
         let mut element = Element::default();
-        let mut representer = Representer::new();
-        let mut searched_len = std::usize::MAX;
+        let mut searched_end = 0;
 
-        for (i, b) in self.buffer.iter().enumerate() {
-            if rows_cnt == top + height {
-                break;
+        for (i, b) in (start..end).zip(self.buffer.iter_from(start)) {
+            if i < searched_end {
+                continue;
             }
 
-            if rows_cnt >= top {
-                if *b == NEWLINE {
-                    element.push(*b);
-                    representer.push(element);
-                    element = Element::default();
-                    rows_cnt += 1;
+            if breaks.binary_search(&i).is_ok() {
+                element.push(NEWLINE);
+                let next = element.same_type();
+                representer.push(std::mem::replace(&mut element, next));
+            }
 
-                    continue;
+            // the region starts or ends here
+            if region.contains(&i) != (element.t == ElementType::Region) {
+                if !element.is_empty() {
+                    representer.push(element);
                 }
+                element = if region.contains(&i) {
+                    Element::region_type()
+                } else {
+                    Element::default()
+                };
+            }
 
-                if !self.isearch.ids.is_empty() {
-                    match self.isearch.ids.binary_search(&i) {
-                        Ok(j) => {
-                            searched_len = i;
-                            let mut iter = self.buffer.iter();
-                            iter.seek(i);
-                            let mut searched = Element::isearch_type(j == self.isearch.current);
-
-                            for _ in 0..self.isearch.term.len() {
-                                searched.push(*iter.next().unwrap());
-                            }
+            if b == NEWLINE {
+                element.push(b);
+                representer.push(element);
+                element = Element::default();
 
-                            if !element.is_empty() {
-                                representer.push(element);
-                            }
-                            representer.push(searched);
+                continue;
+            }
 
-                            element = Element::default();
-                        }
-                        Err(_) => {
-                            if i > searched_len && i < searched_len + self.isearch.term.len() {
-                                continue;
-                            }
+            match matches.binary_search_by_key(&i, |r| r.start) {
+                Ok(j) => {
+                    searched_end = matches[j].end;
+                    let mut searched = Element::isearch_type(j == self.isearch.current);
+                    if !element.is_empty() {
+                        representer.push(element);
+                    }
 
-                            element.push(*b);
+                    let bytes = self.buffer.iter_from(i).take(matches[j].len());
+                    for (k, b) in (i..).zip(bytes) {
+                        if k > i && breaks.binary_search(&k).is_ok() {
+                            searched.push(NEWLINE);
+                            let next = searched.same_type();
+                            representer.push(std::mem::replace(&mut searched, next));
                         }
+                        searched.push(b);
                     }
-                } else {
-                    element.push(*b);
-                }
-            }
+                    representer.push(searched);
 
-            if *b == NEWLINE {
-                rows_cnt += 1;
+                    element = Element::default();
+                }
+                Err(_) => element.push(b),
             }
         }
 
@@ -235,208 +972,1001 @@ impl Editor {
         representer
     }
 
+    // read-only copy of the current text that can be sent to another thread
+    pub fn snapshot(&mut self) -> Snapshot {
+        match &self.snapshot {
+            Some(snapshot) if snapshot.version() == self.buffer.version() => snapshot.clone(),
+            _ => {
+                let snapshot = self.buffer.snapshot();
+                self.snapshot = Some(snapshot.clone());
+
+                snapshot
+            }
+        }
+    }
+
+    // the file is written from a snapshot on another thread, so the user can keep typing
     fn save(&mut self) {
-        let tmp_path = format!("{}{}", &self.path, ".tmp");
-        let mut tmp = File::create(&tmp_path).expect("BUG!");
-        tmp.write_all(self.buffer.before_insertion_point())
-            .expect("BUG!");
-        tmp.write_all(self.buffer.after_insertion_point())
-            .expect("BUG!");
-
-        // does not work if the original file changed its mount point during the editing, but who cares?
-        fs::rename(tmp_path, &self.path).expect("BUG!");
-
-        self.undo_manager.push(Command::Checkpoint);
-        self.flags |= SAVED_MASK;
+        self.finish_jobs();
+
+        let snapshot = self.snapshot();
+        let path = self.path.clone();
+        let (encoding, line_ending) = (self.encoding, self.line_ending);
+        self.save_job = Some(thread::spawn(move || {
+            write_file(&path, &snapshot, encoding, line_ending)
+        }));
+
+        self.undo_manager.checkpoint();
         self.flags &= !(DIRTY_MASK);
     }
 
-    fn insert_newline(&mut self) {
-        self.insert_char(NEWLINE as char);
-    }
+    // picks up the results of finished background jobs, true when there was one
+    pub fn poll_jobs(&mut self) -> bool {
+        let saved = self.save_job.as_ref().is_some_and(|job| job.is_finished());
+        if saved {
+            self.finish_save();
+        }
 
-    // FIX: this is not the best way to handle tab
-    fn insert_tab(&mut self) {
-        for _ in 0..4 {
-            self.insert_char(' ');
+        let searched = self.isearch.is_finished();
+        if searched {
+            self.finish_search();
         }
-    }
 
-    fn handle_search(&mut self, d: Option<char>) {
-        if let Some(id) = self.isearch.run(&self.buffer, d) {
-            self.buffer.jump(id);
+        let pasted = self
+            .paste_job
+            .as_ref()
+            .is_some_and(|(_, job)| job.is_finished());
+        if pasted {
+            self.finish_paste(true);
         }
-    }
 
-    fn insert_char(&mut self, c: char) {
-        self.flags |= DIRTY_MASK;
-        let prev_iptr = self.buffer.iptr;
-        self.buffer.insert(c);
+        let copied = self.copy_job.as_ref().is_some_and(|job| job.is_finished());
+        if copied {
+            self.finish_copy();
+        }
 
-        self.undo_manager
-            .push(Command::Insert((prev_iptr, c.to_string())));
+        saved || searched || pasted || copied
     }
 
-    fn delete_before_cursor(&mut self) {
-        self.flags |= DIRTY_MASK;
+    // waits for the background save, search and clipboard read and write, if any
+    pub fn finish_jobs(&mut self) {
+        self.finish_save();
+        self.finish_search();
+        self.finish_paste(false);
+        while self.copy_job.is_some() {
+            self.finish_copy();
+        }
+    }
 
-        if let Some(bytes) = self.buffer.delete_before_ptr() {
-            let prev_iptr = self.buffer.iptr;
-            self.undo_manager
-                .push(Command::DeleteBefore((prev_iptr, bytes)));
+    fn finish_search(&mut self) {
+        if let Some(i) = self.isearch.finish(self.buffer.as_mut()) {
+            self.buffer.jump(i);
         }
     }
 
-    fn delete_under_cursor(&mut self) {
-        self.flags |= DIRTY_MASK;
+    fn finish_save(&mut self) {
+        let Some(job) = self.save_job.take() else {
+            return;
+        };
 
-        if let Some(bytes) = self.buffer.delete_after_ptr() {
-            let prev_iptr = self.buffer.iptr;
-            self.undo_manager
-                .push(Command::DeleteAfter((prev_iptr, bytes)));
+        match job.join().expect("save thread panicked") {
+            Ok(hash) => {
+                self.flags |= SAVED_MASK;
+                self.disk_hash = Some(hash);
+                self.store_undo_history();
+            }
+            Err(e) => {
+                self.flags |= DIRTY_MASK;
+                self.disk_hash = None;
+                self.echo = Some(format!("could not save {}: {}", self.path, e));
+            }
         }
     }
 
-    fn cut_to_eol(&mut self) {
-        let mut cols: usize = 0;
-        for b in self.buffer.after_insertion_point().iter() {
-            if *b == NEWLINE {
-                break;
-            } else {
-                cols += 1;
-            }
+    // the history is only worth keeping if it can be matched with the file on disk
+    fn store_undo_history(&mut self) {
+        let Some(hash) = self.disk_hash else {
+            return;
+        };
+
+        if self.undo_manager.is_empty() {
+            return;
         }
 
-        for _ in 0..=cols.saturating_sub(1) {
-            self.delete_under_cursor();
+        if let Err(e) =
+            undo_file::store(&self.path, self.config.undo_file, hash, &self.undo_manager)
+        {
+            self.echo = Some(format!("could not store the undo history: {}", e));
         }
+    }
 
-        let mut cols: usize = 0;
-        for b in self.buffer.before_insertion_point().iter().rev() {
-            if *b == NEWLINE {
-                break;
-            } else {
-                cols += 1;
-            }
+    // waits for the pending save and keeps the undo history for the next session
+    pub fn close(&mut self) {
+        for mut doc in std::mem::take(&mut self.documents) {
+            self.swap_document(&mut doc);
+            self.close_buffer();
+            self.swap_document(&mut doc);
         }
 
-        if cols == 0 {
-            self.delete_before_cursor();
+        self.close_buffer();
+    }
+
+    fn close_buffer(&mut self) {
+        self.finish_jobs();
+        self.store_undo_history();
+    }
+
+    // the buffer is untouched, the new encoding is used from the next save on
+    fn set_encoding(&mut self, name: &str) {
+        let Some(encoding) = Encoding::from_name(name) else {
+            self.echo = Some(format!("unknown encoding: {}", name.trim()));
+            return;
+        };
+
+        self.echo = if encoding.can_encode(&self.snapshot().text()) {
+            Some(format!("encoding set to {}", encoding.name()))
+        } else {
+            Some(format!(
+                "some chars do not exist in {}, they will be saved as '?'",
+                encoding.name()
+            ))
+        };
+
+        if encoding != self.encoding {
+            self.encoding = encoding;
+            self.flags |= DIRTY_MASK;
         }
     }
 
-    fn undo(&mut self) {
-        self.undo_manager.undo(&mut self.buffer);
+    // like the encoding, only the next save is affected
+    fn set_line_ending(&mut self, name: &str) {
+        let Some(line_ending) = LineEnding::from_name(name) else {
+            self.echo = Some(format!("unknown line ending: {}", name.trim()));
+            return;
+        };
+
+        self.echo = Some(format!("line ending set to {}", line_ending.name()));
+        if line_ending != self.line_ending {
+            self.line_ending = line_ending;
+            self.flags |= DIRTY_MASK;
+        }
     }
 
-    fn redo(&mut self) {
-        self.undo_manager.redo(&mut self.buffer);
+    fn insert_newline(&mut self) {
+        self.insert_char(NEWLINE as char);
     }
 
-    fn forward_one_char(&mut self) {
-        self.buffer.move_ptr_forward();
+    // FIX: this is not the best way to handle tab
+    fn insert_tab(&mut self) {
+        self.insert_str("    ");
     }
 
-    fn backward_one_char(&mut self) {
-        self.buffer.move_ptr_backward();
+    // the matches are looked for on another thread and picked up by `poll_jobs`
+    fn handle_search(&mut self, d: Option<char>) {
+        let snapshot = self.snapshot();
+        self.isearch.run(self.buffer.as_mut(), snapshot, d);
     }
 
-    // TODO: Optimize + unit tests
-    fn jump_to_eol(&mut self) {
-        let mut cols = 0;
-        for b in self.buffer.after_insertion_point().iter() {
-            if *b == NEWLINE {
-                break;
-            } else {
-                cols += 1;
-            }
-        }
+    fn insert_char(&mut self, c: char) {
+        let mut tmp = [0; 4];
+        self.insert_str(c.encode_utf8(&mut tmp));
+    }
 
-        for _ in 0..cols {
-            self.forward_one_char();
+    fn insert_str(&mut self, s: &str) {
+        let prev_iptr = self.buffer.cursor();
+        self.buffer.insert_str(s);
+
+        self.undo_manager.push(
+            Command::Insert((prev_iptr, s.to_string())),
+            prev_iptr,
+            self.buffer.cursor(),
+        );
+    }
+
+    fn delete_before_cursor(&mut self) {
+        let before = self.buffer.cursor();
+        if let Some(bytes) = self.buffer.delete_grapheme_before_ptr() {
+            let prev_iptr = self.buffer.cursor();
+            self.undo_manager
+                .push(Command::DeleteBefore((prev_iptr, bytes)), before, prev_iptr);
         }
     }
 
-    // TODO: Optimize + unit tests
-    fn jump_to_bol(&mut self) {
-        let mut cols = 0;
-        for b in self.buffer.before_insertion_point().iter().rev() {
-            if *b == NEWLINE {
-                break;
-            } else {
-                cols += 1;
-            }
+    // deletes a single code point, e.g. only the accent of a decomposed "é"
+    fn delete_code_point_before_cursor(&mut self) {
+        let before = self.buffer.cursor();
+        if let Some(bytes) = self.buffer.delete_before_ptr() {
+            let prev_iptr = self.buffer.cursor();
+            self.undo_manager
+                .push(Command::DeleteBefore((prev_iptr, bytes)), before, prev_iptr);
         }
+    }
 
-        for _ in 0..cols {
-            self.backward_one_char();
+    fn delete_under_cursor(&mut self) {
+        if let Some(bytes) = self.buffer.delete_grapheme_after_ptr() {
+            let prev_iptr = self.buffer.cursor();
+            self.undo_manager.push(
+                Command::DeleteAfter((prev_iptr, bytes)),
+                prev_iptr,
+                prev_iptr,
+            );
         }
     }
 
-    // TODO: too slow, need optimization + unit tests
-    fn jump_to_next_line(&mut self) {
+    // kills the rest of the line, or the newline itself when already at the end of the line
+    // undone in one step
+    fn cut_to_eol(&mut self) {
+        self.undo_manager.begin_group();
+        let i = self.buffer.cursor();
         let point = self.get_current_point();
-        self.jump_to_eol();
-        self.forward_one_char();
+        let mut end = self.buffer.line_end(point.line);
+        if end == i {
+            end = (i + 1).min(self.buffer.len());
+        }
 
-        let mut cols = 0;
-        for b in self.buffer.after_insertion_point().iter() {
-            if *b == NEWLINE {
-                break;
-            } else {
-                cols += 1;
+        self.kill(i..end);
+        if point.offset == 0 && i > 0 {
+            self.kill(self.buffer.prev_grapheme_boundary(i)..i);
+        }
+        self.undo_manager.end_group();
+    }
+
+    // deletes `range`, which starts or ends at the cursor, into the kill ring
+    // right after another kill it adds to the same entry
+    fn kill(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let before = self.buffer.cursor();
+        let forward = range.start == before;
+        let bytes = self.buffer.delete_range(range.clone());
+        let s = String::from_utf8_lossy(&bytes).into_owned();
+        let cmd = if forward {
+            Command::DeleteAfter((range.start, bytes))
+        } else {
+            Command::DeleteBefore((range.start, bytes))
+        };
+        self.undo_manager.push(cmd, before, range.start);
+
+        if self.chain == Chain::Kill || self.next_chain == Chain::Kill {
+            self.kill_ring.add(&s, forward);
+        } else {
+            self.kill_ring.push(s);
+        }
+        self.next_chain = Chain::Kill;
+        self.share_kill();
+    }
+
+    // the end of the word at or after `i`
+    // only the text after `i` is read, in a window that doubles until the word ends
+    // before its last cluster, which may be cut short or lack the one after it
+    fn word_end(&self, i: usize) -> usize {
+        let mut n = WORD_WINDOW_LEN;
+        loop {
+            let (start, text) = self.buffer.str_between(i, i + n);
+            let end = self.config.words.forward(&text, i - start);
+            if start + text.len() == self.buffer.len()
+                || text[end..].graphemes(true).nth(1).is_some()
+            {
+                return start + end;
+            }
+
+            n *= 2;
+        }
+    }
+
+    // the start of the word before `i`, read backwards in the same kind of window
+    fn word_start(&self, i: usize) -> usize {
+        let mut n = WORD_WINDOW_LEN;
+        loop {
+            let (start, text) = self.buffer.str_between(i.saturating_sub(n), i);
+            let k = self.config.words.backward(&text, text.len());
+            if start == 0 || text[..k].graphemes(true).nth(1).is_some() {
+                return start + k;
+            }
+
+            n *= 2;
+        }
+    }
+
+    fn kill_word(&mut self, forward: bool) {
+        let i = self.buffer.cursor();
+        if forward {
+            let end = self.word_end(i);
+            self.kill(i..end);
+        } else {
+            let start = self.word_start(i);
+            self.kill(start..i);
+        }
+    }
+
+    // swaps the words around the cursor, or before it at the end of a line, and
+    // leaves the cursor after them
+    fn transpose_words(&mut self) {
+        let end = self.word_end(self.buffer.cursor());
+        let second = self.word_start(end);
+        let first = self.word_start(second);
+        let first_end = self.word_end(first);
+        if first == second || first_end > second {
+            self.echo = Some(String::from("no words to transpose"));
+            return;
+        }
+
+        let (_, a) = self.buffer.str_between(first, first_end);
+        let (_, between) = self.buffer.str_between(first_end, second);
+        let (_, b) = self.buffer.str_between(second, end);
+        self.replace(first..end, &format!("{}{}{}", b, between, a));
+    }
+
+    // changes the case from the cursor to the end of the word, and moves after it
+    fn change_case(&mut self, case: Case) {
+        let i = self.buffer.cursor();
+        let end = self.word_end(i);
+        let (_, s) = self.buffer.str_between(i, end);
+        let changed = match case {
+            Case::Upper => s.to_uppercase(),
+            Case::Lower => s.to_lowercase(),
+            Case::Capital => {
+                let mut word = false;
+                s.chars()
+                    .map(|c| {
+                        let upper = !word;
+                        word = c.is_alphanumeric();
+                        if upper {
+                            c.to_uppercase().collect::<String>()
+                        } else {
+                            c.to_lowercase().collect()
+                        }
+                    })
+                    .collect()
+            }
+        };
+
+        self.replace(i..end, &changed);
+    }
+
+    // puts `s` in place of `range`, in one undo step, and the cursor after it
+    fn replace(&mut self, range: Range<usize>, s: &str) {
+        let (_, old) = self.buffer.str_between(range.start, range.end);
+        if old == s {
+            self.buffer.jump(range.end);
+            return;
+        }
+
+        self.undo_manager.begin_group();
+        let before = self.buffer.cursor();
+        let bytes = self.buffer.delete_range(range.clone());
+        self.undo_manager.push(
+            Command::DeleteAfter((range.start, bytes)),
+            before,
+            range.start,
+        );
+        self.buffer.jump(range.start);
+        self.insert_str(s);
+        self.undo_manager.end_group();
+    }
+
+    // puts the newest kill on the system clipboard
+    // a local program is run on another thread and checked on by `poll_jobs`, only one
+    // at a time so that an older kill never ends up on top
+    fn share_kill(&mut self) {
+        let Some(s) = self.kill_ring.newest() else {
+            return;
+        };
+
+        if !self.config.clipboard.can_stall() {
+            if let Err(e) = self.config.clipboard.copy(s) {
+                self.echo = Some(format!("could not copy to the clipboard: {}", e));
             }
+            return;
+        }
+
+        if self.copy_job.is_some() {
+            self.copy_pending = true;
+            return;
+        }
+
+        let clipboard = self.config.clipboard.clone();
+        let s = s.to_string();
+        self.copy_job = Some(thread::spawn(move || clipboard.copy(&s)));
+    }
+
+    // waits for the clipboard write, then writes the kills made in the meantime
+    fn finish_copy(&mut self) {
+        let Some(job) = self.copy_job.take() else {
+            return;
+        };
+
+        if let Err(e) = job.join().expect("copy thread panicked") {
+            self.echo = Some(format!("could not copy to the clipboard: {}", e));
+        }
+
+        if std::mem::take(&mut self.copy_pending) {
+            self.share_kill();
+        }
+    }
+
+    // sets the mark at the cursor and highlights the region, or stops highlighting it
+    // when the mark is already there
+    fn set_mark(&mut self) {
+        let i = self.buffer.cursor();
+        if self.mark_active && self.mark.and_then(|m| self.buffer.marks().get(m)) == Some(i) {
+            self.mark_active = false;
+            return;
+        }
+
+        self.push_mark();
+        self.mark_active = true;
+    }
+
+    // moves the mark to the cursor without highlighting the region
+    fn push_mark(&mut self) {
+        if let Some(mark) = self.mark.take() {
+            self.buffer.marks_mut().remove(mark);
+        }
+        let i = self.buffer.cursor();
+        self.mark = Some(self.buffer.marks_mut().create(i, Gravity::Left));
+        self.echo = Some(String::from("mark set"));
+    }
+
+    // between the mark and the cursor, while it is highlighted
+    pub fn region(&self) -> Option<Range<usize>> {
+        if !self.mark_active {
+            return None;
+        }
+
+        self.mark_range()
+    }
+
+    // between the mark and the cursor, highlighted or not
+    fn mark_range(&self) -> Option<Range<usize>> {
+        let mark = self.buffer.marks().get(self.mark?)?;
+        let i = self.buffer.cursor();
+
+        Some(mark.min(i)..mark.max(i))
+    }
+
+    fn kill_region(&mut self) {
+        match self.mark_range() {
+            Some(range) => self.kill(range),
+            None => self.echo = Some(String::from("the mark is not set")),
+        }
+    }
+
+    fn copy_region(&mut self) {
+        let Some(range) = self.mark_range() else {
+            self.echo = Some(String::from("the mark is not set"));
+            return;
+        };
+
+        let bytes = self.buffer.slice(range);
+        self.kill_ring
+            .push(String::from_utf8_lossy(&bytes).into_owned());
+        self.mark_active = false;
+        self.share_kill();
+    }
+
+    // pastes the newest kill, as an undo step of its own
+    // text copied by another program since then comes first, the clipboard is read on
+    // another thread and the yank is done when it is finished
+    fn yank(&mut self) {
+        // our own kill is still being written, nothing newer can be on the clipboard
+        if !self.config.clipboard.can_paste() || self.copy_job.is_some() {
+            self.yank_newest();
+            return;
+        }
+
+        if self.paste_job.is_none() {
+            let clipboard = self.config.clipboard.clone();
+            let i = self.buffer.cursor();
+            let mark = self.buffer.marks_mut().create(i, Gravity::Left);
+            self.paste_job = Some((mark, thread::spawn(move || clipboard.paste())));
+        }
+    }
+
+    // the yank goes where it was asked for, the cursor stays where the user moved it
+    // `between_commands` is false when a command waits for the job, the chain that
+    // command leaves behind is kept then
+    fn finish_paste(&mut self, between_commands: bool) {
+        let Some((mark, job)) = self.paste_job.take() else {
+            return;
+        };
+
+        if let Some(s) = job
+            .join()
+            .expect("paste thread panicked")
+            .map(LineEnding::normalize)
+            && self.kill_ring.newest() != Some(s.as_str())
+        {
+            self.kill_ring.push(s);
+        }
+
+        let cursor = self.buffer.cursor();
+        let at = self.buffer.marks().get(mark).unwrap_or(cursor);
+        self.buffer.marks_mut().remove(mark);
+        let chain = std::mem::take(&mut self.next_chain);
+
+        self.buffer.jump(at);
+        self.yank_newest();
+        if cursor != at {
+            let n = self.buffer.cursor() - at;
+            let i = if cursor > at { cursor + n } else { cursor };
+            self.buffer.jump(i);
+        }
+
+        if between_commands {
+            self.chain = std::mem::replace(&mut self.next_chain, chain);
+        } else {
+            self.next_chain = chain;
         }
+    }
+
+    fn yank_newest(&mut self) {
+        let Some(s) = self.kill_ring.yank().map(str::to_string) else {
+            self.echo = Some(String::from("kill ring is empty"));
+            return;
+        };
+
+        let start = self.buffer.cursor();
+        self.undo_manager.boundary();
+        self.insert_str(&s);
+        self.undo_manager.boundary();
+        self.next_chain = Chain::Yank(start..self.buffer.cursor());
+    }
+
+    // text the terminal pasted, as an undo step of its own
+    fn paste(&mut self, s: &str) {
+        self.undo_manager.boundary();
+        self.insert_str(&LineEnding::normalize(s.to_string()));
+        self.undo_manager.boundary();
+    }
+
+    // replaces what was just yanked with the kill before it
+    fn yank_pop(&mut self) {
+        let Chain::Yank(range) = self.chain.clone() else {
+            self.echo = Some(String::from("previous command was not a yank"));
+            return;
+        };
+
+        let Some(s) = self.kill_ring.pop().map(str::to_string) else {
+            return;
+        };
+
+        self.undo_manager.begin_group();
+        let bytes = self.buffer.delete_range(range.clone());
+        self.undo_manager.push(
+            Command::DeleteBefore((range.start, bytes)),
+            range.end,
+            range.start,
+        );
+        self.buffer.jump(range.start);
+        self.insert_str(&s);
+        self.undo_manager.end_group();
+        self.undo_manager.boundary();
+        self.next_chain = Chain::Yank(range.start..self.buffer.cursor());
+    }
+
+    fn undo(&mut self) {
+        self.undo_manager.undo(self.buffer.as_mut());
+        self.recenter = true;
+    }
+
+    fn redo(&mut self) {
+        self.undo_manager.redo(self.buffer.as_mut());
+        self.recenter = true;
+    }
+
+    fn switch_branch(&mut self, forward: bool) {
+        if !self
+            .undo_manager
+            .switch_branch(self.buffer.as_mut(), forward)
+        {
+            self.echo = Some(String::from("no other branch"));
+        }
+        self.recenter = true;
+    }
+
+    fn jump_to_state(&mut self, id: usize) {
+        self.undo_manager.jump(self.buffer.as_mut(), id);
+        self.recenter = true;
+    }
+
+    fn undo_to_time(&mut self, input: &str) {
+        let Some(ago) = parse_duration(input) else {
+            self.echo = Some(format!("unknown time: {}", input.trim()));
+            return;
+        };
+
+        let time = SystemTime::now() - ago;
+        self.jump_to_state(self.undo_manager.node_at(time));
+    }
+
+    // one user-perceived char, i.e. one extended grapheme cluster
+    fn forward_one_char(&mut self) {
+        let i = self.buffer.next_grapheme_boundary(self.buffer.cursor());
+        self.buffer.jump(i);
+    }
+
+    fn backward_one_char(&mut self) {
+        let i = self.buffer.prev_grapheme_boundary(self.buffer.cursor());
+        self.buffer.jump(i);
+    }
+
+    fn jump_to_eol(&mut self) {
+        let line = self.get_current_point().line;
+        self.buffer.jump(self.buffer.line_end(line));
+    }
+
+    fn jump_to_bol(&mut self) {
+        let line = self.get_current_point().line;
+        self.buffer.jump(self.buffer.line_start(line).unwrap_or(0));
+    }
 
-        for _ in 0..point.0.min(cols) {
-            self.forward_one_char();
+    // keep the display column when possible, otherwise stop at the end of the target line
+    fn jump_to_line(&mut self, line: usize, col: usize) {
+        let Some(start) = self.buffer.line_start(line) else {
+            return;
+        };
+        let (_, s) = self.buffer.str_between(start, self.buffer.line_end(line));
+
+        self.buffer.jump(start + width::byte_at_col(&s, col));
+    }
+
+    fn jump_to_next_line(&mut self) {
+        let (row, col) = self.cursor_row(self.get_current_point());
+
+        match self.rows_forward(row, 1) {
+            (next, 1) => self.jump_to_row(next, col),
+            _ => self.jump_to_eol(),
         }
     }
 
-    // TODO: too slow, need optimization + unit tests
     fn jump_to_previous_line(&mut self) {
-        let point = self.get_current_point();
-        self.jump_to_bol();
-        self.backward_one_char();
+        let (row, col) = self.cursor_row(self.get_current_point());
+
+        match self.rows_back(row, 1) {
+            previous if previous != row => self.jump_to_row(previous, col),
+            _ => self.jump_to_bol(),
+        }
+    }
+
+    // puts the cursor on `row`, at the display column `col` of the row when possible
+    fn jump_to_row(&mut self, (line, row): Row, col: usize) {
+        if !self.wrap {
+            return self.jump_to_line(line, col);
+        }
+        let Some(start) = self.buffer.line_start(line) else {
+            return;
+        };
+
+        let starts = self.row_starts(line);
+        let row = row.min(starts.len() - 1);
+        let from = start + starts[row];
+        let to = starts
+            .get(row + 1)
+            .map_or(self.buffer.line_end(line), |r| start + r);
+        let (_, s) = self.buffer.str_between(from, to);
+
+        // the end of a row is the start of the next one, stay on this one
+        let mut i = from + width::byte_at_col(&s, col);
+        if i == to && row + 1 < starts.len() {
+            i = self.buffer.prev_grapheme_boundary(i);
+        }
+        self.buffer.jump(i);
+    }
+
+    // where the rows of `line` start, from the start of the line
+    // they are cached until the text or the width changes
+    fn row_starts(&self, line: usize) -> Rc<[usize]> {
+        if !self.wrap {
+            return Rc::new([0]);
+        }
+        let Some(start) = self.buffer.line_start(line) else {
+            return Rc::new([0]);
+        };
+
+        self.rows
+            .borrow_mut()
+            .get(line, self.buffer.version(), self.width, || {
+                let (_, s) = self.buffer.str_between(start, self.buffer.line_end(line));
+                s
+            })
+    }
+
+    fn row_offset(&self, (line, row): Row) -> usize {
+        let start = self.buffer.line_start(line).unwrap_or(self.buffer.len());
+        let starts = self.row_starts(line);
+
+        start + starts[row.min(starts.len() - 1)]
+    }
+
+    // the row of the cursor, and its display column in that row
+    fn cursor_row(&self, point: Point) -> (Row, usize) {
+        if !self.wrap {
+            return ((point.line, 0), point.col);
+        }
+
+        let starts = self.row_starts(point.line);
+        let row = starts.partition_point(|r| *r <= point.offset) - 1;
+        let start = self.buffer.line_start(point.line).unwrap_or(0);
+        let (_, s) = self
+            .buffer
+            .str_between(start + starts[row], start + point.offset);
 
-        let mut cols: usize = 0;
-        for b in self.buffer.before_insertion_point().iter().rev() {
-            if *b == NEWLINE {
+        ((point.line, row), width::advance(&s, 0))
+    }
+
+    // the row `n` rows below `row`, or the last one, and how many rows that is
+    fn rows_forward(&self, (mut line, mut row): Row, n: usize) -> (Row, usize) {
+        let last = self.buffer.line_count() - 1;
+        if !self.wrap {
+            let moved = n.min(last.saturating_sub(line));
+            return ((line + moved, 0), moved);
+        }
+
+        let mut count = self.row_starts(line).len();
+        let mut moved = 0;
+        while moved < n {
+            if row + 1 < count {
+                row += 1;
+            } else if line < last {
+                line += 1;
+                row = 0;
+                count = self.row_starts(line).len();
+            } else {
                 break;
+            }
+            moved += 1;
+        }
+
+        ((line, row), moved)
+    }
+
+    // the row `n` rows above `row`, or the first one
+    fn rows_back(&self, (mut line, mut row): Row, n: usize) -> Row {
+        if !self.wrap {
+            return (line.saturating_sub(n), 0);
+        }
+
+        for _ in 0..n {
+            if row > 0 {
+                row -= 1;
+            } else if line > 0 {
+                line -= 1;
+                row = self.row_starts(line).len() - 1;
             } else {
-                cols += 1;
+                break;
+            }
+        }
+
+        (line, row)
+    }
+
+    // how many rows `to` is below `from`
+    fn rows_between(&self, from: Row, to: Row) -> usize {
+        if !self.wrap {
+            return to.0.saturating_sub(from.0);
+        }
+
+        let (mut row, mut n) = (from, 0);
+        while row < to {
+            match self.rows_forward(row, 1) {
+                (next, 1) => row = next,
+                _ => break,
             }
+            n += 1;
+        }
+
+        n
+    }
+
+    // scrolls the window by a page, less two rows that stay on screen, and brings the
+    // cursor along when it goes out of the margins
+    // when the buffer cannot scroll further, goes to its beginning or end instead
+    fn scroll_page(&mut self, forward: bool) {
+        let height = self.height.max(1);
+        let page = height.saturating_sub(2).max(1);
+        let top = (self.top, self.top_row);
+
+        if forward && self.rows_forward(top, height).1 < height {
+            self.buffer.jump(self.buffer.len());
+            return;
+        } else if !forward && top == (0, 0) {
+            self.buffer.jump(0);
+            return;
+        }
+
+        let top = if forward {
+            self.rows_forward(top, page).0
+        } else {
+            self.rows_back(top, page)
+        };
+        (self.top, self.top_row) = top;
+
+        let margin = self.scroll_margin(height);
+        let (cursor, col) = self.cursor_row(self.get_current_point());
+        let first = if top == (0, 0) {
+            top
+        } else {
+            self.rows_forward(top, margin).0
+        };
+        let (last, _) = self.rows_forward(top, height - 1 - margin);
+        let row = cursor.max(first).min(last);
+        if row != cursor {
+            self.jump_to_row(row, col);
+        }
+    }
+
+    // puts the cursor row in the middle of the window, then at the top and at the
+    // bottom when pressed again
+    fn recenter(&mut self) {
+        let n = match self.chain {
+            Chain::Recenter(n) => n + 1,
+            _ => 0,
+        };
+        let height = self.height.max(1);
+        let margin = self.scroll_margin(height);
+        let (cursor, _) = self.cursor_row(self.get_current_point());
+
+        (self.top, self.top_row) = match n % 3 {
+            0 => self.rows_back(cursor, height / 2),
+            1 => self.rows_back(cursor, margin),
+            _ => self.rows_back(cursor, height - 1 - margin),
+        };
+        self.next_chain = Chain::Recenter(n);
+    }
+
+    // wraps long lines at the window width instead of scrolling them sideways
+    fn toggle_wrap(&mut self) {
+        self.wrap = !self.wrap;
+        self.left = 0;
+        self.top_row = 0;
+        self.echo = Some(String::from(if self.wrap {
+            "wrapping long lines"
+        } else {
+            "truncating long lines"
+        }));
+    }
+
+    pub fn is_wrapping(&self) -> bool {
+        self.wrap
+    }
+
+    // leaves the mark behind, so that the region covers what was jumped over
+    fn jump_far(&mut self, i: usize) {
+        if !self.mark_active {
+            self.push_mark();
         }
 
-        for _ in 0..cols.saturating_sub(point.0) {
-            self.backward_one_char();
+        self.buffer.jump(i);
+        self.recenter = true;
+    }
+
+    // counted from 1, like the mode line and compilers do
+    fn goto_line(&mut self, input: &str) {
+        match input.trim().parse::<usize>() {
+            Ok(n) if n > 0 => {
+                let line = (n - 1).min(self.buffer.line_count() - 1);
+                self.jump_far(self.buffer.line_start(line).unwrap_or(0));
+            }
+            _ => self.echo = Some(format!("not a line number: {}", input.trim())),
         }
     }
 }
 
+// "90", "90s", "5m", "2h", "1d", optionally followed by "ago"
+fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let s = s.strip_suffix("ago").unwrap_or(s).trim_end();
+    let (n, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+
+    let secs = match unit.trim() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+
+    Some(Duration::from_secs(n.parse::<u64>().ok()? * secs))
+}
+
+fn is_modified(flags: i8, undo_manager: &UndoManager) -> bool {
+    flags & DIRTY_MASK == DIRTY_MASK || !undo_manager.is_at_checkpoint()
+}
+
+// `path` with `.`, `..` and symlinks resolved, to tell whether two paths are the same
+// file; only made absolute when the file does not exist
+fn canonical(path: &str) -> PathBuf {
+    fs::canonicalize(path)
+        .or_else(|_| path::absolute(path))
+        .unwrap_or_else(|_| PathBuf::from(path))
+}
+
+fn write_file(
+    path: &str,
+    snapshot: &Snapshot,
+    encoding: Encoding,
+    line_ending: LineEnding,
+) -> io::Result<u64> {
+    let tmp_path = format!("{}{}", path, ".tmp");
+    let mut tmp = File::create(&tmp_path)?;
+    let text = snapshot.text();
+    let hash = if encoding == Encoding::Utf8 && line_ending == LineEnding::Lf {
+        tmp.write_all(text.as_bytes())?;
+        undo_file::hash(text.as_bytes())
+    } else {
+        let bytes = encoding.encode(&line_ending.apply(&text));
+        tmp.write_all(&bytes)?;
+        undo_file::hash(&bytes)
+    };
+
+    // does not work if the original file changed its mount point during the editing, but who cares?
+    fs::rename(tmp_path, path)?;
+
+    Ok(hash)
+}
+
 #[derive(Debug)]
 struct ISearch {
     term: String,
-    ids: Vec<usize>,
+    // (start, end) of every match
+    // the marks keep them on the matched text even if the text around them changes
+    matches: Vec<(MarkId, MarkId)>,
     current: usize,
+    // (start, end) of the text searched, all of it when none
+    scope: Option<(MarkId, MarkId)>,
+    // the version of the text searched, and the thread finding the matches in it
+    job: Option<(u64, JoinHandle<Vec<Range<usize>>>)>,
 }
 
 impl ISearch {
     fn new() -> Self {
         Self {
             term: String::with_capacity(64),
-            ids: Vec::with_capacity(32),
+            matches: Vec::with_capacity(32),
             current: 0,
+            scope: None,
+            job: None,
         }
     }
 
-    fn clear(&mut self) {
+    fn clear(&mut self, buf: &mut dyn TextStorage) {
         self.term.clear();
-        self.ids.clear();
+        self.job = None;
+        self.clear_matches(buf);
         self.current = 0;
+        if let Some((start, end)) = self.scope.take() {
+            buf.marks_mut().remove(start);
+            buf.marks_mut().remove(end);
+        }
+    }
+
+    fn clear_matches(&mut self, buf: &mut dyn TextStorage) {
+        for (start, end) in self.matches.drain(..) {
+            buf.marks_mut().remove(start);
+            buf.marks_mut().remove(end);
+        }
     }
 
-    fn run(&mut self, buf: &Buffer, d: Option<char>) -> Option<usize> {
+    fn ranges(&self, buf: &dyn TextStorage) -> Vec<Range<usize>> {
+        self.matches
+            .iter()
+            .filter_map(|(start, end)| Some(buf.marks().get(*start)?..buf.marks().get(*end)?))
+            .collect()
+    }
+
+    fn start_of(&self, buf: &dyn TextStorage, j: usize) -> Option<usize> {
+        buf.marks().get(self.matches.get(j)?.0)
+    }
+
+    // `snapshot` is the current contents of `buf`, the search runs on another thread
+    // and replaces the previous one if it is still running
+    fn run(&mut self, buf: &mut dyn TextStorage, snapshot: Snapshot, d: Option<char>) {
         match d {
             Some(c) => {
                 self.term.push(c);
@@ -447,35 +1977,60 @@ impl ISearch {
         }
 
         if self.term.is_empty() {
-            self.clear();
-
-            None
+            self.job = None;
+            self.clear_matches(buf);
+            self.current = 0;
         } else {
-            let tmp = &mut buf.before_insertion_point().to_vec();
-            tmp.extend(buf.after_insertion_point());
-            let s = str::from_utf8(&tmp).unwrap();
+            let scope = match self.scope {
+                Some((start, end)) => {
+                    let start = buf.marks().get(start).unwrap_or(0);
+                    start..buf.marks().get(end).unwrap_or(start).max(start)
+                }
+                None => 0..buf.len(),
+            };
+            let term = self.term.clone();
+            let version = snapshot.version();
+            let job = thread::spawn(move || snapshot.find(&term, scope));
+
+            self.job = Some((version, job));
+        }
+    }
 
-            self.ids = s
-                .match_indices(&self.term)
-                .map(|(i, _)| i)
-                .collect::<Vec<usize>>();
+    fn is_finished(&self) -> bool {
+        self.job.as_ref().is_some_and(|(_, job)| job.is_finished())
+    }
 
-            if self.ids.is_empty() {
-                None
-            } else {
-                Some(self.ids[self.current])
-            }
+    // waits for the search thread and keeps its matches, unless the text changed since
+    // then the start of the current match
+    fn finish(&mut self, buf: &mut dyn TextStorage) -> Option<usize> {
+        let (version, job) = self.job.take()?;
+        let found = job.join().expect("search thread panicked");
+        if version != buf.version() {
+            return None;
+        }
+
+        self.clear_matches(buf);
+        for r in found {
+            let start = buf.marks_mut().create(r.start, Gravity::Right);
+            let end = buf.marks_mut().create(r.end, Gravity::Left);
+            self.matches.push((start, end));
+        }
+
+        if self.current >= self.matches.len() {
+            self.current = 0;
         }
+
+        self.start_of(buf, self.current)
     }
 
-    fn fetch_next(&mut self) -> Option<usize> {
-        if self.ids.is_empty() {
+    fn fetch_next(&mut self, buf: &dyn TextStorage) -> Option<usize> {
+        if self.matches.is_empty() {
             None
         } else {
             self.current += 1;
-            self.current %= self.ids.len();
+            self.current %= self.matches.len();
 
-            Some(self.ids[self.current])
+            self.start_of(buf, self.current)
         }
     }
 }
@@ -494,43 +2049,43 @@ mod tests {
 
         // TODO: This is not the correct way to test this method
         assert_eq!(
-            editor.viewable_contents(1).decorate().to_string(),
+            editor.viewable_contents(80, 1).decorate().to_string(),
             "Hello world"
         );
         assert_eq!(
-            editor.viewable_contents(2).decorate().to_string(),
+            editor.viewable_contents(80, 2).decorate().to_string(),
             "Hello world\nThis is a test"
         );
         assert_eq!(
-            editor.viewable_contents(3).decorate().to_string(),
+            editor.viewable_contents(80, 3).decorate().to_string(),
             "Hello world\nThis is a test\nGood night!"
         );
 
         editor.top = 1;
         assert_eq!(
-            editor.viewable_contents(1).decorate().to_string(),
+            editor.viewable_contents(80, 1).decorate().to_string(),
             "This is a test"
         );
         assert_eq!(
-            editor.viewable_contents(2).decorate().to_string(),
+            editor.viewable_contents(80, 2).decorate().to_string(),
             "This is a test\nGood night!"
         );
         assert_eq!(
-            editor.viewable_contents(3).decorate().to_string(),
+            editor.viewable_contents(80, 3).decorate().to_string(),
             "This is a test\nGood night!"
         );
 
         editor.top = 2;
         assert_eq!(
-            editor.viewable_contents(1).decorate().to_string(),
+            editor.viewable_contents(80, 1).decorate().to_string(),
             "Good night!"
         );
         assert_eq!(
-            editor.viewable_contents(2).decorate().to_string(),
+            editor.viewable_contents(80, 2).decorate().to_string(),
             "Good night!"
         );
         assert_eq!(
-            editor.viewable_contents(3).decorate().to_string(),
+            editor.viewable_contents(80, 3).decorate().to_string(),
             "Good night!"
         );
     }
@@ -544,7 +2099,7 @@ mod tests {
         editor.backward_one_char();
         editor.forward_one_char();
 
-        assert_eq!(editor.viewable_contents(43).decorate().to_string(), "a");
+        assert_eq!(editor.viewable_contents(80, 43).decorate().to_string(), "a");
     }
 
     #[test]
@@ -552,14 +2107,14 @@ mod tests {
         let buffer = String::from("mod buffer;\n\nBufReader");
         let path = String::from("test_tmp.txt");
         let mut editor = Editor::new(buffer, path);
-        dbg!(&editor.viewable_contents(43));
+        dbg!(&editor.viewable_contents(80, 43));
 
         editor.toggle_prompt();
         editor.insert_char('B');
         editor.insert_char('u');
 
-        dbg!(&editor.viewable_contents(43));
-        dbg!(&editor.viewable_contents(43).decorate().to_string());
+        dbg!(&editor.viewable_contents(80, 43));
+        dbg!(&editor.viewable_contents(80, 43).decorate().to_string());
     }
 
     #[test]
@@ -569,8 +2124,9 @@ mod tests {
         let mut editor = Editor::new(buffer, path);
         editor.jump_to_bol();
 
-        assert_eq!(editor.buffer.iptr, 0);
-        assert_eq!(editor.get_current_point(), (0, 0));
+        assert_eq!(editor.buffer.cursor(), 0);
+        let p = editor.get_current_point();
+        assert_eq!((p.col, p.line), (0, 0));
     }
 
     #[test]
@@ -580,16 +2136,19 @@ mod tests {
         let mut editor = Editor::new(buffer, path);
         editor.jump_to_bol();
 
-        assert_eq!(editor.buffer.iptr, 0);
-        assert_eq!(editor.get_current_point(), (0, 0));
+        assert_eq!(editor.buffer.cursor(), 0);
+        let p = editor.get_current_point();
+        assert_eq!((p.col, p.line), (0, 0));
 
         editor.forward_one_char();
-        assert_eq!(editor.buffer.iptr, 1);
-        assert_eq!(editor.get_current_point(), (1, 0));
+        assert_eq!(editor.buffer.cursor(), 1);
+        let p = editor.get_current_point();
+        assert_eq!((p.col, p.line), (1, 0));
 
         editor.jump_to_bol();
-        assert_eq!(editor.buffer.iptr, 0);
-        assert_eq!(editor.get_current_point(), (0, 0));
+        assert_eq!(editor.buffer.cursor(), 0);
+        let p = editor.get_current_point();
+        assert_eq!((p.col, p.line), (0, 0));
     }
 
     #[test]
@@ -600,7 +2159,8 @@ mod tests {
 
         editor.forward_one_char();
         editor.jump_to_next_line();
-        assert_eq!(editor.get_current_point(), (0, 1));
+        let p = editor.get_current_point();
+        assert_eq!((p.col, p.line), (0, 1));
     }
 
     #[test]
@@ -613,7 +2173,8 @@ mod tests {
         editor.jump_to_next_line();
 
         editor.jump_to_previous_line();
-        assert_eq!(editor.get_current_point(), (0, 1));
+        let p = editor.get_current_point();
+        assert_eq!((p.col, p.line), (0, 1));
     }
 
     #[test]
@@ -623,25 +2184,29 @@ mod tests {
         let mut editor = Editor::new(buffer, path);
 
         editor.toggle_prompt();
-        assert!(editor.isearch.ids.is_empty());
+        assert!(editor.isearch.matches.is_empty());
 
         editor.update(Message::Insert('o'));
-        assert_eq!(editor.isearch.ids, vec![4, 7, 20]);
+        editor.finish_jobs();
+        assert_eq!(
+            editor.isearch.ranges(editor.buffer.as_ref()),
+            vec![4..5, 7..8, 20..21]
+        );
 
         let p = editor.get_current_point();
-        assert_eq!(p, (4, 0));
+        assert_eq!((p.col, p.line), (4, 0));
 
         editor.update(Message::InsertNewLine);
         let p = editor.get_current_point();
-        assert_eq!(p, (7, 0));
+        assert_eq!((p.col, p.line), (7, 0));
 
         editor.update(Message::InsertNewLine);
         let p = editor.get_current_point();
-        assert_eq!(p, (7, 2));
+        assert_eq!((p.col, p.line), (7, 2));
 
         editor.update(Message::InsertNewLine);
         let p = editor.get_current_point();
-        assert_eq!(p, (4, 0));
+        assert_eq!((p.col, p.line), (4, 0));
 
         editor.toggle_prompt();
         editor.update(Message::Insert('z'));
@@ -664,4 +2229,1019 @@ mod tests {
 
         assert_eq!(editor.buffer.to_string(), "hello wor\n");
     }
+
+    #[test]
+    fn test_editor_insert_tab() {
+        let buffer = String::from("a");
+        let path = String::from("test_tmp");
+        let mut editor = Editor::new(buffer, path);
+
+        editor.update(Message::InsertTab);
+        assert_eq!(editor.buffer.to_string(), "    a");
+        assert_eq!(editor.buffer.cursor(), 4);
+
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "a");
+
+        editor.update(Message::Redo);
+        assert_eq!(editor.buffer.to_string(), "    a");
+    }
+
+    #[test]
+    fn test_editor_cut_to_eol() {
+        let buffer = String::from("hello world\nfoo");
+        let path = String::from("test_tmp");
+        let mut editor = Editor::new(buffer, path);
+
+        editor.update(Message::ForwardOneChar);
+        editor.update(Message::CutToEndOfLine);
+        assert_eq!(editor.buffer.to_string(), "h\nfoo");
+
+        editor.update(Message::CutToEndOfLine);
+        assert_eq!(editor.buffer.to_string(), "hfoo");
+
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "hello world\nfoo");
+        assert_eq!(editor.buffer.cursor(), 1);
+
+        editor.update(Message::Redo);
+        assert_eq!(editor.buffer.to_string(), "hfoo");
+    }
+
+    #[test]
+    fn test_editor_cut_to_eol_2() {
+        let buffer = String::from("ab\ncd\nef");
+        let mut editor = Editor::new(buffer, String::from("test_tmp"));
+        editor.update(Message::JumpToNextLine);
+
+        // the rest of the line and the newline before it, undone in one step
+        editor.update(Message::CutToEndOfLine);
+        assert_eq!(editor.buffer.to_string(), "ab\nef");
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "ab\ncd\nef");
+        editor.update(Message::Redo);
+        assert_eq!(editor.buffer.to_string(), "ab\nef");
+    }
+
+    #[test]
+    fn test_editor_kill_ring() {
+        let buffer = String::from("ab\ncd\nef");
+        let mut editor = Editor::new(buffer, String::from("test_tmp"));
+        editor.update(Message::Yank);
+        assert_eq!(editor.echo(), Some("kill ring is empty"));
+
+        // consecutive kills make up one entry
+        editor.update(Message::CutToEndOfLine);
+        editor.update(Message::CutToEndOfLine);
+        editor.update(Message::JumpToNextLine);
+        editor.update(Message::CutToEndOfLine);
+        assert_eq!(editor.buffer.to_string(), "cd");
+
+        editor.update(Message::JumpToEndOfLine);
+        editor.update(Message::YankPop);
+        assert_eq!(editor.echo(), Some("previous command was not a yank"));
+        editor.update(Message::Yank);
+        assert_eq!(editor.buffer.to_string(), "cd\nef");
+        editor.update(Message::YankPop);
+        assert_eq!(editor.buffer.to_string(), "cdab\n");
+        editor.update(Message::YankPop);
+        assert_eq!(editor.buffer.to_string(), "cd\nef");
+        assert_eq!(editor.buffer.cursor(), 5);
+
+        // each yank-pop, then the yank, are undone one at a time
+        editor.update(Message::Insert('!'));
+        editor.update(Message::Undo);
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "cdab\n");
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "cd\nef");
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "cd");
+    }
+
+    #[test]
+    fn test_editor_region() {
+        let buffer = String::from("one two\nthree two");
+        let mut editor = Editor::new(buffer, String::from("test_tmp"));
+        editor.update(Message::KillRegion);
+        assert_eq!(editor.echo(), Some("the mark is not set"));
+
+        editor.update(Message::ForwardOneChar);
+        editor.update(Message::SetMark);
+        editor.update(Message::JumpToNextLine);
+        assert_eq!(editor.region(), Some(1..9));
+        assert_eq!(
+            editor
+                .viewable_contents(80, 2)
+                .decorate()
+                .lines
+                .iter()
+                .map(|line| line.spans.len())
+                .collect::<Vec<usize>>(),
+            vec![2, 2]
+        );
+
+        // the search only finds what is in the region
+        editor.update(Message::Search);
+        assert!(editor.is_search_in_region());
+        assert_eq!(editor.region(), None);
+        editor.update(Message::Insert('t'));
+        editor.finish_jobs();
+        assert_eq!(
+            editor.isearch.ranges(editor.buffer.as_ref()),
+            vec![4..5, 8..9]
+        );
+        editor.update(Message::Search);
+        assert!(!editor.is_search_in_region());
+
+        // the search left the cursor on its match
+        editor.update(Message::CopyRegion);
+        editor.update(Message::KillRegion);
+        assert_eq!(editor.buffer.to_string(), "otwo\nthree two");
+        editor.update(Message::Yank);
+        editor.update(Message::YankPop);
+        assert_eq!(editor.buffer.to_string(), "one two\nthree two");
+        assert_eq!(editor.region(), None);
+
+        // setting the mark twice at the same place hides the region
+        editor.update(Message::SetMark);
+        editor.update(Message::SetMark);
+        assert_eq!(editor.region(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_editor_clipboard() {
+        use crate::clipboard::Clipboard;
+
+        fs::create_dir_all("tmp").unwrap();
+        let path = "tmp/test_editor_clipboard";
+        let mut editor = Editor::new(String::from("ab\ncd"), String::from("test_tmp"));
+        editor.config.clipboard = Clipboard::Command {
+            copy: format!("tee {}", path),
+            paste: format!("cat {}", path),
+        };
+
+        editor.update(Message::CutToEndOfLine);
+        editor.finish_jobs();
+        assert_eq!(fs::read_to_string(path).unwrap(), "ab");
+
+        // copied by another program
+        fs::write(path, "x\r\ny").unwrap();
+        editor.update(Message::Yank);
+        while editor.paste_job.is_some() {
+            editor.poll_jobs();
+        }
+        assert_eq!(editor.buffer.to_string(), "x\ny\ncd");
+        editor.update(Message::YankPop);
+        assert_eq!(editor.buffer.to_string(), "ab\ncd");
+        fs::remove_file(path).unwrap();
+
+        editor.update(Message::Paste(String::from("1\r2")));
+        editor.update(Message::Insert('3'));
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "ab1\n2\ncd");
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "ab\ncd");
+
+        // the yank goes where it was asked for, even after typing in the meantime
+        fs::write(path, "x\r\ny").unwrap();
+        editor.update(Message::Yank);
+        editor.update(Message::Insert('!'));
+        while editor.paste_job.is_some() {
+            editor.poll_jobs();
+        }
+        assert_eq!(editor.buffer.to_string(), "abx\ny!\ncd");
+        assert_eq!(editor.buffer.cursor(), 6);
+
+        // waited for by a command such as save, the yank does not chain with the next one
+        editor.update(Message::Yank);
+        editor.finish_jobs();
+        assert_eq!(editor.buffer.to_string(), "abx\ny!x\ny\ncd");
+        editor.update(Message::YankPop);
+        assert_eq!(editor.echo(), Some("previous command was not a yank"));
+        fs::remove_file(path).unwrap();
+
+        // the copy runs in the background, its failure shows up once it is done
+        editor.config.clipboard = Clipboard::Command {
+            copy: String::from("false"),
+            paste: String::new(),
+        };
+        editor.update(Message::CutToEndOfLine);
+        editor.update(Message::CutToEndOfLine);
+        assert_eq!(editor.echo(), None);
+        while editor.copy_job.is_some() {
+            editor.poll_jobs();
+        }
+        assert!(editor.echo().unwrap().starts_with("could not copy"));
+    }
+
+    #[test]
+    fn test_editor_word_commands() {
+        let buffer = String::from("foo bar_baz, quxQuux");
+        let mut editor = Editor::new(buffer, String::from("test_tmp"));
+        editor.update(Message::ForwardWord);
+        editor.update(Message::ForwardWord);
+        assert_eq!(editor.buffer.cursor(), 11);
+        editor.update(Message::BackwardWord);
+        assert_eq!(editor.buffer.cursor(), 4);
+
+        editor.update(Message::TransposeWords);
+        assert_eq!(editor.buffer.to_string(), "bar_baz foo, quxQuux");
+        assert_eq!(editor.buffer.cursor(), 11);
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "foo bar_baz, quxQuux");
+
+        editor.config.words.subwords = true;
+        editor.update(Message::JumpToBeginningOfLine);
+        editor.update(Message::CapitalizeWord);
+        editor.update(Message::UpcaseWord);
+        editor.update(Message::ForwardWord);
+        editor.update(Message::ForwardWord);
+        editor.update(Message::DowncaseWord);
+        assert_eq!(editor.buffer.to_string(), "Foo BAR_baz, quxquux");
+
+        // consecutive kills in both directions add up to one kill
+        editor.update(Message::BackwardKillWord);
+        assert_eq!(editor.buffer.to_string(), "Foo BAR_baz, ");
+        editor.update(Message::BackwardWord);
+        editor.update(Message::KillWord);
+        editor.update(Message::BackwardKillWord);
+        assert_eq!(editor.buffer.to_string(), "Foo , ");
+        editor.update(Message::Yank);
+        assert_eq!(editor.buffer.to_string(), "Foo BAR_baz, ");
+        editor.update(Message::YankPop);
+        assert_eq!(editor.buffer.to_string(), "Foo quxquux, ");
+    }
+
+    #[test]
+    fn test_editor_word_window() {
+        // words and blank runs longer than the window, a cluster cut by its edge
+        let buffer = format!(
+            "{}cafe\u{301}{} a{} z",
+            " ".repeat(600),
+            "x".repeat(300),
+            "\u{301}".repeat(200)
+        );
+        let mut editor = Editor::new(buffer, String::from("test_tmp"));
+        editor.update(Message::ForwardWord);
+        assert_eq!(editor.buffer.cursor(), 906);
+        editor.update(Message::ForwardWord);
+        assert_eq!(editor.buffer.cursor(), 1308);
+
+        editor.update(Message::BackwardWord);
+        assert_eq!(editor.buffer.cursor(), 907);
+        editor.update(Message::BackwardWord);
+        assert_eq!(editor.buffer.cursor(), 600);
+        editor.update(Message::BackwardWord);
+        assert_eq!(editor.buffer.cursor(), 0);
+    }
+
+    #[test]
+    fn test_editor_grapheme_movement() {
+        let buffer = String::from("a🧑\u{200D}🌾e\u{301}");
+        let path = String::from("test_tmp");
+        let mut editor = Editor::new(buffer, path);
+
+        editor.update(Message::ForwardOneChar);
+        editor.update(Message::ForwardOneChar);
+        assert_eq!(editor.buffer.cursor(), 12);
+
+        editor.update(Message::DeleteUnderCursor);
+        assert_eq!(editor.buffer.to_string(), "a🧑\u{200D}🌾");
+
+        editor.update(Message::DeleteBeforeCursor);
+        assert_eq!(editor.buffer.to_string(), "a");
+
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "a🧑\u{200D}🌾");
+
+        editor.update(Message::BackwardOneChar);
+        assert_eq!(editor.buffer.cursor(), 1);
+
+        editor.update(Message::ForwardOneChar);
+        editor.update(Message::DeleteCodePointBeforeCursor);
+        assert_eq!(editor.buffer.to_string(), "a🧑\u{200D}");
+
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "a🧑\u{200D}🌾");
+    }
+
+    #[test]
+    fn test_editor_current_point() {
+        let buffer = String::from("\ta日e\u{301}🧑\u{200D}🌾b");
+        let path = String::from("test_tmp");
+        let mut editor = Editor::new(buffer, path);
+
+        let expected = [
+            (0, 0, 0),
+            (1, 1, 4),
+            (2, 2, 5),
+            (5, 3, 7),
+            (8, 5, 8),
+            (19, 8, 10),
+            (20, 9, 11),
+        ];
+        for (offset, index, col) in expected {
+            let p = editor.get_current_point();
+            assert_eq!((p.offset, p.index, p.col), (offset, index, col));
+            editor.forward_one_char();
+        }
+    }
+
+    #[test]
+    fn test_editor_jump_to_next_line_2() {
+        let buffer = String::from("日本語\nabcdef\na\tb");
+        let path = String::from("test_tmp");
+        let mut editor = Editor::new(buffer, path);
+
+        editor.forward_one_char();
+        editor.forward_one_char();
+        editor.jump_to_next_line();
+        let p = editor.get_current_point();
+        assert_eq!((p.col, p.line), (4, 1));
+
+        editor.forward_one_char();
+        editor.jump_to_previous_line();
+        let p = editor.get_current_point();
+        assert_eq!((p.offset, p.col, p.line), (6, 4, 0));
+
+        editor.jump_to_next_line();
+        editor.jump_to_next_line();
+        let p = editor.get_current_point();
+        assert_eq!((p.offset, p.col, p.line), (2, 4, 2));
+    }
+
+    #[test]
+    fn test_editor_isearch_matches_follow_edits() {
+        let buffer = String::from("foo bar foo");
+        let path = String::from("test_tmp");
+        let mut editor = Editor::new(buffer, path);
+
+        editor.toggle_prompt();
+        editor.update(Message::Insert('f'));
+        editor.update(Message::Insert('o'));
+        editor.finish_jobs();
+
+        editor.buffer.jump(0);
+        editor.buffer.insert_str(">> ");
+        editor.buffer.delete_range(11..12);
+        assert_eq!(editor.buffer.to_string(), ">> foo bar oo");
+        assert_eq!(
+            editor.isearch.ranges(editor.buffer.as_ref()),
+            vec![3..5, 11..12]
+        );
+        assert_eq!(editor.isearch.fetch_next(editor.buffer.as_ref()), Some(11));
+
+        // the text changed while searching, the matches found are out of date
+        editor.update(Message::Insert('o'));
+        editor.buffer.insert_str("x");
+        editor.finish_jobs();
+        assert_eq!(editor.isearch.matches.len(), 2);
+
+        editor.toggle_prompt();
+        assert!(editor.isearch.matches.is_empty());
+    }
+
+    #[test]
+    fn test_editor_set_encoding() {
+        let path = String::from("tmp/test_editor_set_encoding.txt");
+        let mut editor = Editor::new(String::from("café"), path.clone());
+        assert_eq!(editor.encoding, Encoding::Utf8);
+
+        editor.update(Message::SetEncoding);
+        assert!(editor.minibuffer_prompt().is_some());
+        for c in "utf-16".chars() {
+            editor.update(Message::Insert(c));
+        }
+        editor.update(Message::InsertNewLine);
+        assert_eq!(editor.echo(), Some("unknown encoding: utf-16"));
+        assert_eq!(editor.minibuffer_prompt(), None);
+
+        editor.update(Message::SetEncoding);
+        for c in "latin-1".chars() {
+            editor.update(Message::Insert(c));
+        }
+        editor.update(Message::InsertNewLine);
+        assert_eq!(editor.encoding, Encoding::Windows1252);
+        assert!(editor.is_modified());
+
+        editor.update(Message::Save);
+        editor.finish_jobs();
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xE9");
+        fs::remove_file(&path).unwrap();
+
+        editor.update(Message::SetEncoding);
+        editor.update(Message::Insert('x'));
+        editor.update(Message::Redo);
+        assert_eq!(editor.minibuffer_prompt(), None);
+        assert_eq!(editor.encoding, Encoding::Windows1252);
+    }
+
+    #[test]
+    fn test_editor_snapshot() {
+        let path = String::from("tmp/test_editor_snapshot.txt");
+        let mut editor = Editor::new(String::from("abc"), path.clone());
+        let a = editor.snapshot();
+        let b = editor.snapshot();
+        assert!(std::ptr::eq(a.text().as_ref(), b.text().as_ref()));
+
+        editor.update(Message::Save);
+        editor.update(Message::Insert('x'));
+        assert!(editor.is_modified());
+        assert_eq!(a.text(), "abc");
+        assert_eq!(editor.snapshot().text(), "xabc");
+
+        editor.finish_jobs();
+        assert_eq!(fs::read(&path).unwrap(), b"abc");
+        assert!(editor.is_modified());
+        fs::remove_file(&path).unwrap();
+
+        editor.path = String::from("tmp/no/such/dir/file.txt");
+        editor.update(Message::Save);
+        editor.finish_jobs();
+        assert!(editor.is_modified());
+        assert!(editor.echo().unwrap().starts_with("could not save"));
+    }
+
+    #[test]
+    fn test_editor_line_ending() {
+        let path = String::from("tmp/test_editor_line_ending.txt");
+        let mut editor = Editor::open(b"ab\r\ncd\r\n", path.clone(), Config::default());
+        assert_eq!(editor.line_ending, LineEnding::CrLf);
+        assert_eq!(editor.echo(), None);
+        assert_eq!(editor.buffer.line_count(), 3);
+
+        editor.jump_to_eol();
+        assert_eq!(editor.buffer.cursor(), 2);
+        editor.forward_one_char();
+        assert_eq!(editor.get_current_point().line, 1);
+
+        editor.update(Message::Insert('x'));
+        editor.update(Message::Save);
+        editor.finish_jobs();
+        assert_eq!(fs::read(&path).unwrap(), b"ab\r\nxcd\r\n");
+
+        editor.update(Message::SetLineEnding);
+        for c in "lf".chars() {
+            editor.update(Message::Insert(c));
+        }
+        editor.update(Message::InsertNewLine);
+        assert!(editor.is_modified());
+        editor.update(Message::Save);
+        editor.finish_jobs();
+        assert_eq!(fs::read(&path).unwrap(), b"ab\nxcd\n");
+        fs::remove_file(&path).unwrap();
+
+        let editor = Editor::open(b"a\r\nb\nc\r\n", path, Config::default());
+        assert_eq!(editor.line_ending, LineEnding::CrLf);
+        assert_eq!(
+            editor.echo(),
+            Some("mixed line endings, they will be saved as crlf")
+        );
+    }
+
+    #[test]
+    fn test_editor_undo_tree_browser() {
+        let mut editor = Editor::new(String::new(), String::from("dummy.txt"));
+        editor.update(Message::Insert('a'));
+        editor.update(Message::Undo);
+        editor.update(Message::Insert('b'));
+        assert_eq!(editor.buffer.to_string(), "b");
+
+        editor.update(Message::PreviousBranch);
+        assert_eq!(editor.buffer.to_string(), "a");
+
+        editor.update(Message::UndoTree);
+        assert_eq!(editor.undo_tree().unwrap().len(), 3);
+        editor.update(Message::ForwardOneChar);
+        assert_eq!(editor.buffer.to_string(), "b");
+        editor.update(Message::JumpToPreviousLine);
+        assert_eq!(editor.buffer.to_string(), "");
+
+        // cancelling goes back to where the browser was opened
+        editor.update(Message::Insert('x'));
+        assert_eq!(editor.undo_tree(), None);
+        assert_eq!(editor.buffer.to_string(), "a");
+
+        editor.update(Message::UndoTree);
+        editor.update(Message::BackwardOneChar);
+        editor.update(Message::InsertNewLine);
+        assert_eq!(editor.undo_tree(), None);
+        assert_eq!(editor.buffer.to_string(), "b");
+
+        editor.update(Message::UndoToTime);
+        for c in "1h ago".chars() {
+            editor.update(Message::Insert(c));
+        }
+        editor.update(Message::InsertNewLine);
+        assert_eq!(editor.buffer.to_string(), "");
+    }
+
+    #[test]
+    fn test_editor_parse_duration() {
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration(" 5m ago"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1 d"), Some(Duration::from_secs(86400)));
+        assert_eq!(parse_duration("5 years"), None);
+        assert_eq!(parse_duration("m"), None);
+    }
+
+    #[test]
+    fn test_editor_undo_recenter() {
+        let buffer = "line\n".repeat(100);
+        let mut editor = Editor::new(buffer, String::from("dummy.txt"));
+        for _ in 0..80 {
+            editor.update(Message::JumpToNextLine);
+        }
+        editor.update(Message::Insert('x'));
+        for _ in 0..75 {
+            editor.update(Message::JumpToPreviousLine);
+        }
+        let point = editor.get_current_point();
+        editor.make_cursor_visible(point, 80, 10);
+        assert_eq!(editor.top, 0);
+
+        editor.update(Message::Undo);
+        let point = editor.get_current_point();
+        assert_eq!((point.line, point.col), (80, 0));
+        assert_eq!(editor.make_cursor_visible(point, 80, 10), (0, 5));
+        assert_eq!(editor.top, 75);
+
+        // a cursor that is already visible does not scroll
+        editor.update(Message::Redo);
+        let point = editor.get_current_point();
+        assert_eq!((point.line, point.col), (80, 1));
+        assert_eq!(editor.make_cursor_visible(point, 80, 10), (1, 5));
+    }
+
+    #[test]
+    fn test_editor_scroll_page() {
+        let buffer = "line\n".repeat(100);
+        let mut editor = Editor::new(buffer, String::from("dummy.txt"));
+        editor.make_cursor_visible(editor.get_current_point(), 80, 10);
+
+        // the cursor is brought along, inside the scroll margin
+        editor.update(Message::NextPage);
+        assert_eq!(editor.top, 8);
+        let point = editor.get_current_point();
+        assert_eq!(point.line, 10);
+        assert_eq!(editor.make_cursor_visible(point, 80, 10), (0, 2));
+
+        editor.update(Message::PreviousPage);
+        assert_eq!(editor.top, 0);
+        assert_eq!(editor.get_current_point().line, 7);
+        editor.update(Message::PreviousPage);
+        assert_eq!(editor.buffer.cursor(), 0);
+
+        // moving down scrolls before the cursor reaches the last row
+        for _ in 0..8 {
+            editor.update(Message::JumpToNextLine);
+            editor.make_cursor_visible(editor.get_current_point(), 80, 10);
+        }
+        assert_eq!(editor.top, 5);
+
+        editor.top = 95;
+        editor.update(Message::NextPage);
+        assert_eq!(editor.buffer.cursor(), editor.buffer.len());
+        let point = editor.get_current_point();
+        assert_eq!(editor.make_cursor_visible(point, 80, 10), (0, 5));
+
+        // a window too small for half of it to be scrolled stays put between renders
+        let mut editor = Editor::new("line\n".repeat(20), String::from("dummy.txt"));
+        for _ in 0..3 {
+            editor.update(Message::JumpToNextLine);
+        }
+        let point = editor.get_current_point();
+        assert_eq!(editor.make_cursor_visible(point, 80, 5), (0, 2));
+        assert_eq!(editor.make_cursor_visible(point, 80, 5), (0, 2));
+    }
+
+    #[test]
+    fn test_editor_recenter() {
+        let buffer = "line\n".repeat(100);
+        let mut editor = Editor::new(buffer, String::from("dummy.txt"));
+        for _ in 0..50 {
+            editor.update(Message::JumpToNextLine);
+        }
+        editor.make_cursor_visible(editor.get_current_point(), 80, 10);
+
+        let mut tops = Vec::new();
+        for _ in 0..4 {
+            editor.update(Message::Recenter);
+            tops.push(editor.top);
+        }
+        assert_eq!(tops, vec![45, 48, 43, 45]);
+
+        editor.update(Message::JumpToNextLine);
+        editor.update(Message::Recenter);
+        assert_eq!(editor.top, 46);
+    }
+
+    #[test]
+    fn test_editor_hscroll() {
+        let line = "0123456789".repeat(5);
+        let mut editor = Editor::new(format!("{}\nshort\n", line), String::from("dummy.txt"));
+        for _ in 0..25 {
+            editor.update(Message::ForwardOneChar);
+        }
+
+        // centered, with text hidden on both sides
+        let point = editor.get_current_point();
+        assert_eq!(editor.make_cursor_visible(point, 20, 10), (10, 0));
+        assert_eq!(editor.left, 15);
+        assert_eq!(
+            editor.viewable_contents(20, 2).decorate().to_string(),
+            format!("${}$\n$", &line[16..34])
+        );
+
+        // a cursor that is not on an edge column does not scroll
+        editor.update(Message::ForwardOneChar);
+        let point = editor.get_current_point();
+        assert_eq!(editor.make_cursor_visible(point, 20, 10), (11, 0));
+
+        editor.update(Message::JumpToBeginningOfLine);
+        let point = editor.get_current_point();
+        assert_eq!(editor.make_cursor_visible(point, 20, 10), (0, 0));
+        assert_eq!(editor.left, 0);
+
+        // by steps, centering when a step is not enough
+        editor.config.hscroll_step = 8;
+        for (col, screen, left) in [(19, 11, 8), (18, 10, 8), (40, 10, 30), (29, 7, 22)] {
+            editor.jump_to_line(0, col);
+            let point = editor.get_current_point();
+            assert_eq!(editor.make_cursor_visible(point, 20, 10), (screen, 0));
+            assert_eq!(editor.left, left);
+        }
+    }
+
+    #[test]
+    fn test_editor_hscroll_wide_chars() {
+        let mut editor = Editor::new(String::from("a日本\tb"), String::from("dummy.txt"));
+        editor.left = 2;
+        assert_eq!(
+            editor.viewable_contents(4, 1).decorate().to_string(),
+            "$本$"
+        );
+        editor.left = 1;
+        assert_eq!(
+            editor.viewable_contents(4, 1).decorate().to_string(),
+            "$  $"
+        );
+
+        let mut editor = Editor::new(String::from("a日本"), String::from("dummy.txt"));
+        editor.left = 1;
+        assert_eq!(
+            editor.viewable_contents(4, 1).decorate().to_string(),
+            "$ 本"
+        );
+    }
+
+    #[test]
+    fn test_editor_wrap() {
+        let buffer = String::from("the quick brown fox\nab\n");
+        let mut editor = Editor::new(buffer, String::from("dummy.txt"));
+        editor.update(Message::ToggleWrap);
+        assert_eq!(editor.echo(), Some("wrapping long lines"));
+        editor.make_cursor_visible(editor.get_current_point(), 10, 3);
+        assert_eq!(
+            editor.viewable_contents(10, 3).decorate().to_string(),
+            "the quick \nbrown fox\nab"
+        );
+        assert_eq!(
+            editor.viewable_contents(10, 2).decorate().to_string(),
+            "the quick \nbrown fox"
+        );
+
+        // down and up by screen line, keeping the column
+        for _ in 0..7 {
+            editor.update(Message::ForwardOneChar);
+        }
+        let mut cursors = Vec::new();
+        for message in [
+            Message::JumpToNextLine,
+            Message::JumpToNextLine,
+            Message::JumpToPreviousLine,
+            Message::JumpToPreviousLine,
+        ] {
+            editor.update(message);
+            cursors.push(editor.buffer.cursor());
+        }
+        assert_eq!(cursors, vec![17, 22, 12, 2]);
+
+        editor.update(Message::ToggleWrap);
+        assert_eq!(
+            editor.viewable_contents(10, 3).decorate().to_string(),
+            "the quick$\nab"
+        );
+    }
+
+    #[test]
+    fn test_editor_wrap_rows() {
+        let buffer = String::from("aaaa bbbbbbbb\nab");
+        let mut editor = Editor::new(buffer, String::from("dummy.txt"));
+        editor.update(Message::ToggleWrap);
+        editor.make_cursor_visible(editor.get_current_point(), 6, 10);
+
+        let mut cursors = Vec::new();
+        for _ in 0..3 {
+            editor.update(Message::JumpToNextLine);
+            cursors.push(editor.buffer.cursor());
+        }
+        assert_eq!(cursors, vec![5, 11, 14]);
+
+        // the end of a row is the start of the next one
+        editor.buffer.jump(10);
+        let point = editor.get_current_point();
+        assert_eq!(editor.make_cursor_visible(point, 6, 10), (5, 1));
+        editor.update(Message::JumpToPreviousLine);
+        assert_eq!(editor.buffer.cursor(), 4);
+    }
+
+    #[test]
+    fn test_editor_wrap_scroll() {
+        let buffer = "word word word word word word \n".repeat(10);
+        let mut editor = Editor::new(buffer, String::from("dummy.txt"));
+        editor.update(Message::ToggleWrap);
+        editor.make_cursor_visible(editor.get_current_point(), 10, 5);
+
+        // the window starts in the middle of a line
+        for _ in 0..4 {
+            editor.update(Message::JumpToNextLine);
+            editor.make_cursor_visible(editor.get_current_point(), 10, 5);
+        }
+        assert_eq!((editor.top, editor.top_row), (0, 2));
+        let point = editor.get_current_point();
+        assert_eq!(editor.make_cursor_visible(point, 10, 5), (0, 2));
+
+        editor.update(Message::NextPage);
+        assert_eq!((editor.top, editor.top_row), (1, 2));
+        assert_eq!(editor.buffer.cursor(), 72);
+        let contents = editor.viewable_contents(10, 5).decorate().to_string();
+        assert_eq!(contents, "word word \n".repeat(5).trim_end_matches('\n'));
+
+        editor.config.scroll_margin = 0;
+        let mut tops = Vec::new();
+        for _ in 0..3 {
+            editor.update(Message::Recenter);
+            tops.push((editor.top, editor.top_row));
+        }
+        assert_eq!(tops, vec![(1, 2), (2, 1), (1, 0)]);
+    }
+
+    #[test]
+    fn test_editor_open_buffer_twice() {
+        let dir = "tmp/test_editor_open_buffer_twice";
+        fs::create_dir_all(dir).unwrap();
+        fs::write(format!("{}/a.txt", dir), "one").unwrap();
+
+        let mut editor = Editor::new(String::from("one"), format!("{}/a.txt", dir));
+        editor.open_buffer(
+            b"one",
+            format!("{}/../test_editor_open_buffer_twice/a.txt", dir),
+        );
+        editor.open_buffer(b"one", format!("{}//a.txt", dir));
+        assert_eq!(editor.buffer_names(), vec!["a.txt"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_editor_switch_buffer() {
+        let mut editor = Editor::new(String::from("one"), String::from("a.txt"));
+        editor.open_buffer(b"two", String::from("dir/b.txt"));
+        editor.open_buffer(b"three", String::from("dir/ab.txt"));
+        editor.open_buffer(b"again", String::from("a.txt"));
+        editor.open_buffer(b"again", String::from("./a.txt"));
+        editor.open_buffer(b"again", String::from("dir/./b.txt"));
+        assert_eq!(editor.buffer_names(), vec!["a.txt", "b.txt", "ab.txt"]);
+
+        editor.update(Message::SwitchBuffer);
+        assert_eq!(
+            editor.minibuffer_prompt().as_deref(),
+            Some(" switch to buffer (default b.txt): ")
+        );
+        editor.update(Message::Insert('a'));
+        assert_eq!(
+            editor.minibuffer_completions().as_deref(),
+            Some("{a.txt | ab.txt}")
+        );
+        editor.update(Message::Insert('b'));
+        editor.update(Message::InsertTab);
+        assert_eq!(
+            editor.minibuffer_prompt().as_deref(),
+            Some(" switch to buffer (default b.txt): ab.txt")
+        );
+        editor.update(Message::InsertNewLine);
+        assert_eq!(editor.path, "dir/ab.txt");
+        assert_eq!(editor.buffer.to_string(), "three");
+
+        // each buffer keeps its own text and history
+        editor.update(Message::Insert('!'));
+        editor.update(Message::SwitchBuffer);
+        editor.update(Message::InsertNewLine);
+        assert_eq!(editor.path, "a.txt");
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "one");
+        assert_eq!(editor.buffer_names(), vec!["a.txt", "ab.txt", "b.txt"]);
+
+        editor.update(Message::SwitchBuffer);
+        editor.update(Message::Insert('x'));
+        editor.update(Message::InsertNewLine);
+        assert_eq!(editor.echo(), Some("no buffer named x"));
+        assert_eq!(editor.path, "a.txt");
+
+        let rows = editor.buffer_rows();
+        assert_eq!(
+            rows.iter()
+                .map(|r| (r.size, r.modified))
+                .collect::<Vec<_>>(),
+            vec![(3, false), (6, true), (3, false)]
+        );
+    }
+
+    #[test]
+    fn test_editor_buffer_names() {
+        let mut editor = Editor::new(String::new(), String::from("src/main.rs"));
+        editor.open_buffer(b"", String::from("tests/main.rs"));
+        editor.open_buffer(b"", String::from("README"));
+        assert_eq!(
+            editor.buffer_names(),
+            vec!["main.rs<src>", "main.rs<tests>", "README"]
+        );
+        assert_eq!(editor.find_buffer("main", 0), None);
+        assert_eq!(editor.find_buffer("main.rs<t", 0), Some(1));
+        assert_eq!(editor.find_buffer("", 2), Some(2));
+    }
+
+    #[test]
+    fn test_editor_kill_buffer() {
+        let mut editor = Editor::new(String::from("one"), String::from("a.txt"));
+        editor.update(Message::KillBuffer);
+        editor.update(Message::InsertNewLine);
+        assert_eq!(editor.echo(), Some("cannot kill the only buffer"));
+
+        editor.open_buffer(b"two", String::from("b.txt"));
+        editor.update(Message::Insert('x'));
+        editor.update(Message::KillBuffer);
+        editor.update(Message::InsertNewLine);
+        assert_eq!(
+            editor.minibuffer_prompt().as_deref(),
+            Some(" a.txt is modified, kill anyway? (yes or no): ")
+        );
+        editor.update(Message::Insert('n'));
+        editor.update(Message::InsertNewLine);
+        assert_eq!(editor.echo(), Some("buffer not killed"));
+        assert_eq!(editor.buffer_names().len(), 2);
+
+        editor.update(Message::KillBuffer);
+        editor.update(Message::InsertNewLine);
+        for c in "yes".chars() {
+            editor.update(Message::Insert(c));
+        }
+        editor.update(Message::InsertNewLine);
+        assert_eq!(editor.echo(), Some("killed a.txt"));
+        assert_eq!(editor.path, "b.txt");
+        assert_eq!(editor.buffer_names(), vec!["b.txt"]);
+    }
+
+    #[test]
+    fn test_editor_buffer_list() {
+        let mut editor = Editor::new(String::from("one"), String::from("a.txt"));
+        editor.open_buffer(b"two", String::from("b.txt"));
+        assert_eq!(editor.buffer_list(), None);
+
+        editor.update(Message::BufferList);
+        editor.update(Message::JumpToNextLine);
+        editor.update(Message::JumpToNextLine);
+        let rows = editor.buffer_list().unwrap();
+        assert_eq!(
+            rows[1],
+            BufferRow {
+                name: String::from("b.txt"),
+                size: 3,
+                modified: false,
+                path: String::from("b.txt"),
+                selected: true,
+            }
+        );
+
+        editor.update(Message::InsertNewLine);
+        assert_eq!(editor.buffer_list(), None);
+        assert_eq!(editor.path, "b.txt");
+    }
+
+    #[test]
+    fn test_editor_goto_line() {
+        let buffer = "line\n".repeat(100);
+        let mut editor = Editor::new(buffer, String::from("dummy.txt"));
+        editor.update(Message::ForwardOneChar);
+
+        editor.update(Message::GotoLine);
+        assert_eq!(
+            editor.minibuffer_prompt().as_deref(),
+            Some(" goto line (1-101): ")
+        );
+        editor.update(Message::Insert('4'));
+        editor.update(Message::Insert('2'));
+        editor.update(Message::InsertNewLine);
+        assert_eq!(editor.get_current_point().line, 41);
+        // the mark is left behind, the region is not highlighted
+        assert_eq!(editor.mark_range(), Some(1..205));
+        assert_eq!(editor.region(), None);
+        let point = editor.get_current_point();
+        assert_eq!(editor.make_cursor_visible(point, 80, 10), (0, 5));
+
+        editor.update(Message::GotoLine);
+        editor.update(Message::Insert('x'));
+        editor.update(Message::InsertNewLine);
+        assert_eq!(editor.echo(), Some("not a line number: x"));
+        assert_eq!(editor.get_current_point().line, 41);
+
+        editor.update(Message::EndOfBuffer);
+        assert_eq!(editor.buffer.cursor(), 500);
+        editor.update(Message::BeginningOfBuffer);
+        assert_eq!(editor.buffer.cursor(), 0);
+        assert_eq!(editor.mark_range(), Some(0..500));
+    }
+
+    #[test]
+    fn test_editor_modified_follows_undo() {
+        let path = String::from("tmp/test_editor_modified_follows_undo.txt");
+        let mut editor = Editor::new(String::from("abc"), path.clone());
+        editor.update(Message::Insert('x'));
+        assert!(editor.is_modified());
+        editor.update(Message::Undo);
+        assert!(!editor.is_modified());
+        editor.update(Message::Redo);
+
+        editor.update(Message::Save);
+        editor.finish_jobs();
+        assert!(!editor.is_modified());
+
+        editor.update(Message::Insert('y'));
+        assert!(editor.is_modified());
+        editor.update(Message::Undo);
+        assert!(!editor.is_modified());
+        editor.update(Message::Undo);
+        assert!(editor.is_modified());
+        editor.update(Message::Redo);
+        assert!(!editor.is_modified());
+        fs::remove_file(&path).unwrap();
+
+        // nothing was deleted, nothing changed
+        editor.update(Message::JumpToBeginningOfLine);
+        editor.update(Message::DeleteBeforeCursor);
+        assert!(!editor.is_modified());
+    }
+
+    #[test]
+    fn test_editor_undo_coalesce() {
+        let mut editor = Editor::new(String::new(), String::from("tmp/test.txt"));
+        "hello world"
+            .chars()
+            .for_each(|c| editor.update(Message::Insert(c)));
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "hello ");
+
+        // moving away and back still starts a new step
+        editor.update(Message::BackwardOneChar);
+        editor.update(Message::ForwardOneChar);
+        editor.update(Message::Insert('x'));
+        editor.update(Message::Insert('y'));
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "hello ");
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "");
+    }
+
+    #[test]
+    fn test_editor_undo_file() {
+        let path = String::from("tmp/test_editor_undo_file.txt");
+        let config = Config::parse("undo_file = sidecar");
+        fs::write(&path, "hello").unwrap();
+
+        let mut editor = Editor::open(b"hello", path.clone(), config.clone());
+        editor.update(Message::JumpToEndOfLine);
+        editor.update(Message::Insert('!'));
+        editor.update(Message::Save);
+        editor.update(Message::Insert('?'));
+        editor.close();
+
+        let mut editor = Editor::open(&fs::read(&path).unwrap(), path.clone(), config.clone());
+        assert!(!editor.is_modified());
+        editor.update(Message::Redo);
+        assert_eq!(editor.buffer.to_string(), "hello!?");
+        editor.update(Message::Undo);
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "hello");
+
+        // changed by someone else, the history does not apply anymore
+        fs::write(&path, "bye").unwrap();
+        let mut editor = Editor::open(b"bye", path.clone(), config);
+        editor.update(Message::Undo);
+        assert_eq!(editor.buffer.to_string(), "bye");
+        assert!(!fs::exists("tmp/.test_editor_undo_file.txt.kame-undo").unwrap());
+        fs::remove_file(&path).unwrap();
+    }
 }
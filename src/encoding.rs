@@ -0,0 +1,240 @@
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+// windows-1252 code points of the bytes 0x80..=0x9F
+// the 5 undefined bytes map to the C1 control at the same position, like browsers
+// do, so that every byte survives a decode/encode round trip
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+// encoding of a file on disk, the buffer itself is always utf8
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16LeBom,
+    Utf16Be,
+    Utf16BeBom,
+    // single byte fallback for anything that is not valid utf8, a superset of latin-1
+    Windows1252,
+}
+
+pub const ENCODINGS: [Encoding; 7] = [
+    Encoding::Utf8,
+    Encoding::Utf8Bom,
+    Encoding::Utf16Le,
+    Encoding::Utf16LeBom,
+    Encoding::Utf16Be,
+    Encoding::Utf16BeBom,
+    Encoding::Windows1252,
+];
+
+impl Encoding {
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(UTF8_BOM) {
+            Self::Utf8Bom
+        } else if bytes.starts_with(UTF16LE_BOM) {
+            Self::Utf16LeBom
+        } else if bytes.starts_with(UTF16BE_BOM) {
+            Self::Utf16BeBom
+        } else if let Some(e) = Self::detect_utf16_without_bom(bytes) {
+            e
+        } else if str::from_utf8(bytes).is_ok() {
+            Self::Utf8
+        } else {
+            Self::Windows1252
+        }
+    }
+
+    // mostly ascii text encoded in utf16 has a zero in every other byte
+    fn detect_utf16_without_bom(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+            return None;
+        }
+
+        let units = bytes.len() / 2;
+        let even = bytes.iter().step_by(2).filter(|b| **b == 0).count();
+        let odd = bytes.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+
+        if odd * 4 >= units * 3 && even == 0 {
+            Some(Self::Utf16Le)
+        } else if even * 4 >= units * 3 && odd == 0 {
+            Some(Self::Utf16Be)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Utf8Bom => "utf-8-bom",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16LeBom => "utf-16le-bom",
+            Self::Utf16Be => "utf-16be",
+            Self::Utf16BeBom => "utf-16be-bom",
+            Self::Windows1252 => "windows-1252",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_lowercase();
+        match name.as_str() {
+            "latin-1" | "latin1" | "iso-8859-1" | "cp1252" => Some(Self::Windows1252),
+            _ => ENCODINGS.into_iter().find(|e| e.name() == name),
+        }
+    }
+
+    // invalid sequences are replaced with U+FFFD
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            Self::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Self::Utf8Bom => {
+                String::from_utf8_lossy(bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes)).into_owned()
+            }
+            Self::Utf16Le => Self::decode_utf16(bytes, u16::from_le_bytes),
+            Self::Utf16LeBom => Self::decode_utf16(
+                bytes.strip_prefix(UTF16LE_BOM).unwrap_or(bytes),
+                u16::from_le_bytes,
+            ),
+            Self::Utf16Be => Self::decode_utf16(bytes, u16::from_be_bytes),
+            Self::Utf16BeBom => Self::decode_utf16(
+                bytes.strip_prefix(UTF16BE_BOM).unwrap_or(bytes),
+                u16::from_be_bytes,
+            ),
+            Self::Windows1252 => bytes
+                .iter()
+                .map(|b| match b {
+                    0x80..=0x9F => WINDOWS_1252[(b - 0x80) as usize],
+                    _ => *b as char,
+                })
+                .collect(),
+        }
+    }
+
+    fn decode_utf16(bytes: &[u8], f: fn([u8; 2]) -> u16) -> String {
+        let mut units = bytes.chunks_exact(2).map(|c| f([c[0], c[1]]));
+        let mut s: String = char::decode_utf16(&mut units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+
+        if !bytes.len().is_multiple_of(2) {
+            s.push(char::REPLACEMENT_CHARACTER);
+        }
+
+        s
+    }
+
+    fn encode_1252(c: char) -> Option<u8> {
+        match c as u32 {
+            0..0x80 | 0xA0..0x100 => Some(c as u8),
+            _ => WINDOWS_1252
+                .iter()
+                .position(|m| *m == c)
+                .map(|i| 0x80 + i as u8),
+        }
+    }
+
+    pub fn can_encode(&self, s: &str) -> bool {
+        match self {
+            Self::Windows1252 => s.chars().all(|c| Self::encode_1252(c).is_some()),
+            _ => true,
+        }
+    }
+
+    // chars that do not exist in the target encoding are written as '?'
+    pub fn encode(&self, s: &str) -> Vec<u8> {
+        match self {
+            Self::Utf8 => s.as_bytes().to_vec(),
+            Self::Utf8Bom => [UTF8_BOM, s.as_bytes()].concat(),
+            Self::Utf16Le => s.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            Self::Utf16LeBom => UTF16LE_BOM
+                .iter()
+                .copied()
+                .chain(s.encode_utf16().flat_map(u16::to_le_bytes))
+                .collect(),
+            Self::Utf16Be => s.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            Self::Utf16BeBom => UTF16BE_BOM
+                .iter()
+                .copied()
+                .chain(s.encode_utf16().flat_map(u16::to_be_bytes))
+                .collect(),
+            Self::Windows1252 => s
+                .chars()
+                .map(|c| Self::encode_1252(c).unwrap_or(b'?'))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding_detect_1() {
+        assert_eq!(Encoding::detect(b""), Encoding::Utf8);
+        assert_eq!(Encoding::detect("xin chào".as_bytes()), Encoding::Utf8);
+        assert_eq!(Encoding::detect(b"\xEF\xBB\xBFabc"), Encoding::Utf8Bom);
+        assert_eq!(Encoding::detect(b"\xFF\xFEa\0"), Encoding::Utf16LeBom);
+        assert_eq!(Encoding::detect(b"\xFE\xFF\0a"), Encoding::Utf16BeBom);
+        assert_eq!(Encoding::detect(b"a\0b\0c\0\n\0"), Encoding::Utf16Le);
+        assert_eq!(Encoding::detect(b"\0a\0b\0c\0\n"), Encoding::Utf16Be);
+        assert_eq!(Encoding::detect(b"caf\xE9"), Encoding::Windows1252);
+    }
+
+    #[test]
+    fn test_encoding_decode_1() {
+        assert_eq!(Encoding::Utf8Bom.decode(b"\xEF\xBB\xBFabc"), "abc");
+        assert_eq!(
+            Encoding::Utf16LeBom.decode(b"\xFF\xFEa\0=\xD8\0\xDE"),
+            "a😀"
+        );
+        assert_eq!(
+            Encoding::Utf16BeBom.decode(b"\xFE\xFF\0a\xD8=\xDE\0"),
+            "a😀"
+        );
+        assert_eq!(Encoding::Utf16Le.decode(b"a\0=\xD8\0\xDE"), "a😀");
+        assert_eq!(Encoding::Windows1252.decode(b"caf\xE9 \x80\x96"), "café €–");
+        assert_eq!(Encoding::Utf16Le.decode(b"a\0b"), "a\u{FFFD}");
+    }
+
+    #[test]
+    fn test_encoding_round_trip_1() {
+        let all_bytes = (0..=255).collect::<Vec<u8>>();
+        let s = Encoding::Windows1252.decode(&all_bytes);
+        assert_eq!(Encoding::Windows1252.encode(&s), all_bytes);
+
+        for e in ENCODINGS {
+            let bytes = e.encode("héllo\nwörld ✓");
+            assert_eq!(Encoding::detect(&bytes), e, "{}", e.name());
+            if e != Encoding::Windows1252 {
+                assert_eq!(e.decode(&bytes), "héllo\nwörld ✓");
+            }
+        }
+    }
+
+    #[test]
+    fn test_encoding_round_trip_2() {
+        // a utf16 file without a bom is saved without one
+        for bytes in [&b"a\0b\0\n\0"[..], b"\0a\0b\0\n"] {
+            let e = Encoding::detect(bytes);
+            assert_eq!(e.encode(&e.decode(bytes)), bytes, "{}", e.name());
+        }
+    }
+
+    #[test]
+    fn test_encoding_encode_1() {
+        assert!(!Encoding::Windows1252.can_encode("✓"));
+        assert_eq!(Encoding::Windows1252.encode("é✓"), b"\xE9?");
+        assert_eq!(Encoding::from_name(" UTF-16LE "), Some(Encoding::Utf16Le));
+        assert_eq!(Encoding::from_name("latin-1"), Some(Encoding::Windows1252));
+        assert_eq!(Encoding::from_name("ebcdic"), None);
+    }
+}
@@ -0,0 +1,571 @@
+// Differential testing of the text storages and the undo manager.
+//
+// Random sequences of edits, moves, undos and redos are applied both to a
+// storage driven through `UndoManager` the way the editor drives it, and to a
+// plain `String` model whose undo history is a tree of whole-text states.
+// After every step the two must agree on the text, the cursor and the lines.
+// A failing sequence is shrunk to a minimal one before it is reported.
+
+use std::panic::{self, AssertUnwindSafe};
+
+use unicode_segmentation::GraphemeCursor;
+
+use crate::storage::TextStorage;
+use crate::undo::{Command, UndoManager};
+
+const CASES: u64 = 300;
+const STEPS: usize = 120;
+
+// bits of text the random inserts are made of: multi-byte chars, wide chars,
+// combining marks and multi-code-point clusters
+const PIECES: [&str; 12] = [
+    "a",
+    "b",
+    " ",
+    "\n",
+    "\t",
+    "é",
+    "日",
+    "🙂",
+    "e\u{301}",
+    "\u{301}",
+    "🇻🇳",
+    "👩\u{200D}🔬",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Op {
+    Insert(String),
+    DeleteBefore,
+    DeleteAfter,
+    // replaces the grapheme before the cursor, as one undo group
+    Replace(String),
+    // raw offset, wrapped to the text length and floored to a char boundary
+    Move(usize),
+    Forward,
+    Backward,
+    Undo,
+    Redo,
+    SwitchBranch(bool),
+    // raw node id, wrapped to the number of nodes
+    JumpTo(usize),
+    Save,
+}
+
+// xorshift64*
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn text(&mut self, max_pieces: usize) -> String {
+        (0..=self.below(max_pieces))
+            .map(|_| PIECES[self.below(PIECES.len())])
+            .collect()
+    }
+
+    fn op(&mut self) -> Op {
+        match self.below(23) {
+            0..=5 => Op::Insert(self.text(3)),
+            6..=8 => Op::DeleteBefore,
+            9..=10 => Op::DeleteAfter,
+            11..=12 => Op::Move(self.below(1 << 16)),
+            13 => Op::Forward,
+            14 => Op::Backward,
+            15..=17 => Op::Undo,
+            18 => Op::Redo,
+            19 => Op::SwitchBranch(self.below(2) == 0),
+            20 => Op::JumpTo(self.below(1 << 16)),
+            21 => Op::Replace(self.text(2)),
+            _ => Op::Save,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Insert,
+    DeleteBefore,
+    DeleteAfter,
+    Group,
+}
+
+// one state of the model's undo tree, with the whole text and cursor on both
+// sides of the change that leads to it
+#[derive(Debug, Clone)]
+struct Entry {
+    // none for the root
+    kind: Option<Kind>,
+    at: usize,
+    len: usize,
+    before: (String, usize),
+    after: (String, usize),
+    parent: usize,
+    children: Vec<usize>,
+    active: usize,
+    sealed: bool,
+}
+
+#[derive(Debug)]
+struct Model {
+    text: String,
+    cursor: usize,
+    nodes: Vec<Entry>,
+    current: usize,
+    saved: usize,
+}
+
+impl Model {
+    fn new(text: &str) -> Self {
+        let state = (text.to_string(), 0);
+        let root = Entry {
+            kind: None,
+            at: 0,
+            len: 0,
+            before: state.clone(),
+            after: state,
+            parent: 0,
+            children: Vec::new(),
+            active: 0,
+            sealed: true,
+        };
+
+        Self {
+            text: text.to_string(),
+            cursor: 0,
+            nodes: vec![root],
+            current: 0,
+            saved: 0,
+        }
+    }
+
+    fn state(&self) -> (String, usize) {
+        (self.text.clone(), self.cursor)
+    }
+
+    fn next_boundary(&self) -> usize {
+        let mut cursor = GraphemeCursor::new(self.cursor, self.text.len(), true);
+        cursor
+            .next_boundary(&self.text, 0)
+            .unwrap()
+            .unwrap_or(self.text.len())
+    }
+
+    fn prev_boundary(&self) -> usize {
+        let mut cursor = GraphemeCursor::new(self.cursor, self.text.len(), true);
+        cursor.prev_boundary(&self.text, 0).unwrap().unwrap_or(0)
+    }
+
+    // consecutive typing and deleting at the tip of a branch is undone in one step,
+    // like the undo manager does
+    fn record(&mut self, kind: Kind, at: usize, len: usize, before: (String, usize)) {
+        let after = self.state();
+        let id = self.nodes.len();
+        let parent = self.current;
+        let last = &mut self.nodes[parent];
+
+        if let Some(last_kind) = last.kind
+            && last.children.is_empty()
+            && !last.sealed
+        {
+            let merge = match (last_kind, kind) {
+                (Kind::Insert, Kind::Insert) => last.at + last.len == at,
+                (Kind::DeleteBefore, Kind::DeleteBefore) => at + len == last.at,
+                (Kind::DeleteAfter, Kind::DeleteAfter) => at == last.at,
+                _ => false,
+            };
+
+            if merge {
+                if kind != Kind::Insert {
+                    last.at = at;
+                }
+                last.len += len;
+                last.after = after;
+                return;
+            }
+        }
+
+        last.active = last.children.len();
+        last.children.push(id);
+        self.nodes.push(Entry {
+            kind: Some(kind),
+            at,
+            len,
+            before,
+            after,
+            parent,
+            children: Vec::new(),
+            active: 0,
+            sealed: false,
+        });
+        self.current = id;
+    }
+
+    fn delete(&mut self, kind: Kind, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+
+        let before = self.state();
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+        self.record(kind, start, end - start, before);
+    }
+
+    fn undo(&mut self) {
+        if self.current == 0 {
+            return;
+        }
+
+        let node = &self.nodes[self.current];
+        let parent = node.parent;
+        (self.text, self.cursor) = node.before.clone();
+        self.nodes[parent].active = self.nodes[parent]
+            .children
+            .iter()
+            .position(|c| *c == self.current)
+            .unwrap();
+        self.current = parent;
+    }
+
+    fn redo(&mut self) {
+        let node = &self.nodes[self.current];
+        if let Some(&child) = node.children.get(node.active) {
+            (self.text, self.cursor) = self.nodes[child].after.clone();
+            self.current = child;
+        }
+    }
+
+    fn ancestors(&self, mut id: usize) -> Vec<usize> {
+        let mut path = vec![id];
+        while id != 0 {
+            id = self.nodes[id].parent;
+            path.push(id);
+        }
+
+        path
+    }
+
+    fn apply(&mut self, op: &Op) {
+        match op {
+            Op::Insert(s) => {
+                let before = self.state();
+                self.text.insert_str(self.cursor, s);
+                self.cursor += s.len();
+                self.record(Kind::Insert, before.1, s.len(), before);
+            }
+            Op::DeleteBefore => self.delete(Kind::DeleteBefore, self.prev_boundary(), self.cursor),
+            Op::DeleteAfter => self.delete(Kind::DeleteAfter, self.cursor, self.next_boundary()),
+            Op::Replace(s) => {
+                let before = self.state();
+                let start = self.prev_boundary();
+                self.text.replace_range(start..self.cursor, s);
+                self.cursor = start + s.len();
+
+                // nothing to delete leaves a group of one insert, which merges as usual
+                if start == before.1 {
+                    self.record(Kind::Insert, start, s.len(), before);
+                } else {
+                    self.record(Kind::Group, start, s.len(), before);
+                }
+            }
+            Op::Move(n) => {
+                let mut i = n % (self.text.len() + 1);
+                while !self.text.is_char_boundary(i) {
+                    i -= 1;
+                }
+                self.cursor = i;
+            }
+            Op::Forward => self.cursor = self.next_boundary(),
+            Op::Backward => self.cursor = self.prev_boundary(),
+            Op::Undo => self.undo(),
+            Op::Redo => self.redo(),
+            Op::SwitchBranch(forward) => {
+                let parent = self.nodes[self.current].parent;
+                let siblings = self.nodes[parent].children.clone();
+                if self.current != 0 && siblings.len() > 1 {
+                    let i = siblings.iter().position(|c| *c == self.current).unwrap();
+                    let n = siblings.len();
+                    self.undo();
+                    self.nodes[parent].active = if *forward {
+                        (i + 1) % n
+                    } else {
+                        (i + n - 1) % n
+                    };
+                    self.redo();
+                }
+            }
+            Op::JumpTo(n) => {
+                let path = self.ancestors(n % self.nodes.len());
+                while !path.contains(&self.current) {
+                    self.undo();
+                }
+
+                let i = path.iter().position(|id| *id == self.current).unwrap();
+                for &id in path[..i].iter().rev() {
+                    let children = &self.nodes[self.current].children;
+                    self.nodes[self.current].active =
+                        children.iter().position(|c| *c == id).unwrap();
+                    self.redo();
+                }
+            }
+            Op::Save => {
+                self.nodes[self.current].sealed = true;
+                self.saved = self.current;
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+fn insert(buf: &mut dyn TextStorage, undo: &mut UndoManager, s: &str) {
+    let prev = buf.cursor();
+    buf.insert_str(s);
+    undo.push(Command::Insert((prev, s.to_string())), prev, buf.cursor());
+}
+
+fn delete_before(buf: &mut dyn TextStorage, undo: &mut UndoManager) {
+    let before = buf.cursor();
+    if let Some(bytes) = buf.delete_grapheme_before_ptr() {
+        undo.push(
+            Command::DeleteBefore((buf.cursor(), bytes)),
+            before,
+            buf.cursor(),
+        );
+    }
+}
+
+// does to the storage what the editor does for the same key
+// `nodes` is the size of the model's undo tree, which random jumps are wrapped to
+fn apply(buf: &mut dyn TextStorage, undo: &mut UndoManager, op: &Op, nodes: usize) {
+    match op {
+        Op::Insert(s) => {
+            insert(buf, undo, s);
+        }
+        Op::DeleteBefore => delete_before(buf, undo),
+        Op::DeleteAfter => {
+            let i = buf.cursor();
+            if let Some(bytes) = buf.delete_grapheme_after_ptr() {
+                undo.push(Command::DeleteAfter((i, bytes)), i, i);
+            }
+        }
+        Op::Replace(s) => {
+            undo.begin_group();
+            delete_before(buf, undo);
+            insert(buf, undo, s);
+            undo.end_group();
+        }
+        Op::Move(n) => {
+            let i = buf.floor_char_boundary(n % (buf.len() + 1));
+            buf.jump(i);
+        }
+        Op::Forward => buf.jump(buf.next_grapheme_boundary(buf.cursor())),
+        Op::Backward => buf.jump(buf.prev_grapheme_boundary(buf.cursor())),
+        Op::Undo => undo.undo(buf),
+        Op::Redo => undo.redo(buf),
+        Op::SwitchBranch(forward) => {
+            undo.switch_branch(buf, *forward);
+        }
+        Op::JumpTo(n) => undo.jump(buf, n % nodes),
+        Op::Save => undo.checkpoint(),
+    }
+}
+
+fn check(buf: &mut dyn TextStorage, undo: &UndoManager, model: &Model) -> Result<(), String> {
+    let text = buf.snapshot();
+    if text.text() != model.text.as_str() {
+        return Err(format!(
+            "text is {:?}, expected {:?}",
+            text.text(),
+            model.text
+        ));
+    }
+
+    if undo.is_at_checkpoint() != (model.current == model.saved) {
+        return Err(format!(
+            "saved state is {}, expected {}",
+            undo.is_at_checkpoint(),
+            model.current == model.saved
+        ));
+    }
+
+    if buf.cursor() != model.cursor {
+        return Err(format!(
+            "cursor is {}, expected {}",
+            buf.cursor(),
+            model.cursor
+        ));
+    }
+
+    let starts = std::iter::once(0)
+        .chain(model.text.match_indices('\n').map(|(i, _)| i + 1))
+        .collect::<Vec<usize>>();
+    if buf.line_count() != starts.len() {
+        return Err(format!(
+            "{} lines, expected {}",
+            buf.line_count(),
+            starts.len()
+        ));
+    }
+
+    for (line, start) in starts.into_iter().enumerate() {
+        if buf.line_start(line) != Some(start) {
+            return Err(format!(
+                "line {} starts at {:?}, expected {}",
+                line,
+                buf.line_start(line),
+                start
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// runs `ops` on a fresh storage and model, the error tells at which step they diverged
+fn run(init: fn(String) -> Box<dyn TextStorage>, text: &str, ops: &[Op]) -> Result<(), String> {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut buf = init(text.to_string());
+        let mut undo = UndoManager::new();
+        let mut model = Model::new(text);
+
+        for (step, op) in ops.iter().enumerate() {
+            apply(buf.as_mut(), &mut undo, op, model.len());
+            model.apply(op);
+            check(buf.as_mut(), &undo, &model)
+                .map_err(|e| format!("step {} {:?}: {}", step, op, e))?;
+        }
+
+        Ok(())
+    }));
+
+    result.unwrap_or_else(|_| Err(String::from("panicked")))
+}
+
+// the smallest sequence that still fails: drops chunks of ops, halving the chunk
+// size down to single ops, then makes the remaining inserts as short as possible
+fn shrink(mut ops: Vec<Op>, fails: impl Fn(&[Op]) -> bool) -> Vec<Op> {
+    let mut chunk = ops.len() / 2;
+    while chunk > 0 {
+        let mut i = 0;
+        while i + chunk <= ops.len() {
+            let candidate = [&ops[..i], &ops[i + chunk..]].concat();
+            if fails(&candidate) {
+                ops = candidate;
+            } else {
+                i += 1;
+            }
+        }
+
+        chunk /= 2;
+    }
+
+    for i in 0..ops.len() {
+        while let Op::Insert(s) = &ops[i]
+            && s.chars().count() > 1
+        {
+            let mut candidate = ops.clone();
+            let mut shorter = s.clone();
+            shorter.pop();
+            candidate[i] = Op::Insert(shorter);
+            if !fails(&candidate) {
+                break;
+            }
+
+            ops = candidate;
+        }
+    }
+
+    ops
+}
+
+fn fuzz(init: fn(String) -> Box<dyn TextStorage>) {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let text = rng.text(8);
+        let ops = (0..STEPS).map(|_| rng.op()).collect::<Vec<Op>>();
+
+        if let Err(e) = run(init, &text, &ops) {
+            let ops = shrink(ops, |ops| run(init, &text, ops).is_err());
+            let e = run(init, &text, &ops).err().unwrap_or(e);
+            panic!(
+                "seed {}, initial text {:?}\nops: {:#?}\n{}",
+                seed, text, ops, e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::buffer::Buffer;
+    use crate::piece_table::PieceTable;
+
+    fn gap_buffer(s: String) -> Box<dyn TextStorage> {
+        Box::new(Buffer::init(s))
+    }
+
+    fn piece_table(s: String) -> Box<dyn TextStorage> {
+        Box::new(PieceTable::init(s))
+    }
+
+    #[test]
+    fn test_fuzz_gap_buffer_1() {
+        fuzz(gap_buffer);
+    }
+
+    #[test]
+    fn test_fuzz_piece_table_1() {
+        fuzz(piece_table);
+    }
+
+    #[test]
+    fn test_fuzz_shrink_1() {
+        let ops = vec![
+            Op::Forward,
+            Op::Insert(String::from("ab日")),
+            Op::Undo,
+            Op::Insert(String::from("é🙂")),
+            Op::Save,
+            Op::Redo,
+        ];
+
+        // fails as soon as two inserts add up to 3 chars
+        let fails = |ops: &[Op]| {
+            ops.iter()
+                .map(|op| match op {
+                    Op::Insert(s) => s.chars().count(),
+                    _ => 0,
+                })
+                .sum::<usize>()
+                >= 3
+                && ops.iter().filter(|op| matches!(op, Op::Insert(_))).count() == 2
+        };
+
+        assert_eq!(
+            shrink(ops, fails),
+            vec![
+                Op::Insert(String::from("a")),
+                Op::Insert(String::from("é🙂"))
+            ]
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+
+// how many kills are remembered
+const MAX_ENTRIES: usize = 120;
+
+// Killed text, newest first. Kills made one right after the other add up to a
+// single entry, yank pastes the newest entry and every yank-pop after it an
+// older one, going round to the newest again after the oldest.
+#[derive(Debug, Default)]
+pub struct KillRing {
+    entries: VecDeque<String>,
+    // the entry pasted by the last yank or yank-pop
+    yank: usize,
+}
+
+impl KillRing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, s: String) {
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_back();
+        }
+
+        self.entries.push_front(s);
+    }
+
+    // adds to the newest entry: after its text when killing forward, before it when
+    // killing backward, so that the entry reads as it was in the buffer
+    pub fn add(&mut self, s: &str, forward: bool) {
+        match self.entries.front_mut() {
+            Some(entry) if forward => entry.push_str(s),
+            Some(entry) => entry.insert_str(0, s),
+            None => self.push(s.to_string()),
+        }
+    }
+
+    pub fn newest(&self) -> Option<&str> {
+        self.entries.front().map(String::as_str)
+    }
+
+    pub fn yank(&mut self) -> Option<&str> {
+        self.yank = 0;
+
+        self.entries.front().map(String::as_str)
+    }
+
+    // the entry before the one pasted last
+    pub fn pop(&mut self) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        self.yank = (self.yank + 1) % self.entries.len();
+
+        self.entries.get(self.yank).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kill_ring_1() {
+        let mut ring = KillRing::new();
+        assert_eq!(ring.yank(), None);
+        assert_eq!(ring.pop(), None);
+
+        ring.push(String::from("a"));
+        ring.push(String::from("b"));
+        ring.add("c", true);
+        ring.add("d", false);
+
+        assert_eq!(ring.yank(), Some("dbc"));
+        assert_eq!(ring.pop(), Some("a"));
+        assert_eq!(ring.pop(), Some("dbc"));
+        assert_eq!(ring.pop(), Some("a"));
+        assert_eq!(ring.yank(), Some("dbc"));
+    }
+
+    #[test]
+    fn test_kill_ring_2() {
+        let mut ring = KillRing::new();
+        (0..MAX_ENTRIES + 2).for_each(|i| ring.push(i.to_string()));
+
+        assert_eq!(ring.entries.len(), MAX_ENTRIES);
+        assert_eq!(ring.yank(), Some("121"));
+        for _ in 0..MAX_ENTRIES - 1 {
+            ring.pop();
+        }
+        assert_eq!(ring.pop(), Some("121"));
+    }
+}
@@ -0,0 +1,127 @@
+use std::borrow::Cow;
+
+// line break convention of a file on disk, the buffer itself only uses '\n'
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    // classic mac os
+    Cr,
+}
+
+pub const LINE_ENDINGS: [LineEnding; 3] = [LineEnding::Lf, LineEnding::CrLf, LineEnding::Cr];
+
+impl LineEnding {
+    // the most used style wins, ties go to the first one seen
+    // also tells whether more than one style is used
+    pub fn detect(s: &str) -> (Self, bool) {
+        let bytes = s.as_bytes();
+        let mut counts = [0; 3];
+        let mut first = [usize::MAX; 3];
+        let mut i = 0;
+
+        while i < bytes.len() {
+            let style = match bytes[i] {
+                b'\r' if bytes.get(i + 1) == Some(&b'\n') => {
+                    i += 1;
+                    1
+                }
+                b'\r' => 2,
+                b'\n' => 0,
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+            counts[style] += 1;
+            first[style] = first[style].min(i);
+            i += 1;
+        }
+
+        let used = counts.iter().filter(|n| **n > 0).count();
+        let best = (0..3)
+            .filter(|j| counts[*j] > 0)
+            .max_by_key(|j| (counts[*j], std::cmp::Reverse(first[*j])))
+            .unwrap_or(0);
+
+        (LINE_ENDINGS[best], used > 1)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Lf => "lf",
+            Self::CrLf => "crlf",
+            Self::Cr => "cr",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_lowercase();
+        match name.as_str() {
+            "unix" => Some(Self::Lf),
+            "dos" => Some(Self::CrLf),
+            "mac" => Some(Self::Cr),
+            _ => LINE_ENDINGS.into_iter().find(|e| e.name() == name),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+            Self::Cr => "\r",
+        }
+    }
+
+    // every "\r\n" and lone '\r' of `s` becomes '\n'
+    pub fn normalize(s: String) -> String {
+        if !s.contains('\r') {
+            return s;
+        }
+
+        s.replace("\r\n", "\n").replace('\r', "\n")
+    }
+
+    // the buffer text `s` with its '\n' written in this style
+    pub fn apply<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        match self {
+            Self::Lf => Cow::Borrowed(s),
+            _ => Cow::Owned(s.replace('\n', self.as_str())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_ending_detect_1() {
+        assert_eq!(LineEnding::detect(""), (LineEnding::Lf, false));
+        assert_eq!(LineEnding::detect("a\nb\n"), (LineEnding::Lf, false));
+        assert_eq!(LineEnding::detect("a\r\nb\r\n"), (LineEnding::CrLf, false));
+        assert_eq!(LineEnding::detect("a\rb\r"), (LineEnding::Cr, false));
+        assert_eq!(
+            LineEnding::detect("a\r\nb\nc\r\n"),
+            (LineEnding::CrLf, true)
+        );
+        assert_eq!(LineEnding::detect("a\rb\n"), (LineEnding::Cr, true));
+    }
+
+    #[test]
+    fn test_line_ending_normalize_1() {
+        let s = String::from("a\r\nb\rc\nd\r\r\n");
+        assert_eq!(LineEnding::normalize(s), "a\nb\nc\nd\n\n");
+    }
+
+    #[test]
+    fn test_line_ending_apply_1() {
+        assert_eq!(LineEnding::Lf.apply("a\nb\n"), "a\nb\n");
+        assert_eq!(LineEnding::CrLf.apply("a\nb\n"), "a\r\nb\r\n");
+        assert_eq!(LineEnding::Cr.apply("a\nb"), "a\rb");
+        assert_eq!(LineEnding::from_name(" DOS"), Some(LineEnding::CrLf));
+        assert_eq!(LineEnding::from_name("crlf"), Some(LineEnding::CrLf));
+        assert_eq!(LineEnding::from_name("lfcr"), None);
+    }
+}
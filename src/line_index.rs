@@ -0,0 +1,224 @@
+use std::ops::Range;
+
+const NEWLINE: u8 = b'\n';
+
+// Newline offsets of a text, split at the last edited position the same way the
+// gap buffer is split at its insertion point:
+//
+//   before: absolute offsets of the newlines before the split (ascending)
+//   after:  distances from the end of the text of the newlines after the split
+//           (ascending, so the newline closest to the split is the last one)
+//
+// Edits at the split only touch the tails of both stacks and never shift the
+// other entries. Moving the split costs one pop/push per newline it crosses.
+// Lookups are binary searches on both stacks.
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    before: Vec<usize>,
+    after: Vec<usize>,
+    split: usize,
+    len: usize,
+}
+
+impl LineIndex {
+    pub fn new(bytes: &[u8]) -> Self {
+        let len = bytes.len();
+        let after = bytes
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, b)| **b == NEWLINE)
+            .map(|(i, _)| len - i)
+            .collect();
+
+        Self {
+            before: Vec::new(),
+            after,
+            split: 0,
+            len,
+        }
+    }
+
+    fn move_split(&mut self, offset: usize) {
+        if offset < self.split {
+            while let Some(&p) = self.before.last()
+                && p >= offset
+            {
+                self.before.pop();
+                self.after.push(self.len - p);
+            }
+        } else {
+            while let Some(&d) = self.after.last()
+                && self.len - d < offset
+            {
+                self.after.pop();
+                self.before.push(self.len - d);
+            }
+        }
+
+        self.split = offset;
+    }
+
+    pub fn insert(&mut self, offset: usize, bytes: &[u8]) {
+        self.move_split(offset);
+
+        for (i, b) in bytes.iter().enumerate() {
+            if *b == NEWLINE {
+                self.before.push(offset + i);
+            }
+        }
+
+        self.len += bytes.len();
+        self.split += bytes.len();
+    }
+
+    pub fn delete(&mut self, range: Range<usize>) {
+        self.move_split(range.start);
+
+        while let Some(&d) = self.after.last()
+            && self.len - d < range.end
+        {
+            self.after.pop();
+        }
+
+        self.len -= range.len();
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.before.len() + self.after.len() + 1
+    }
+
+    // number of newlines strictly before `offset`, i.e. the 0-based line of `offset`
+    pub fn line_of(&self, offset: usize) -> usize {
+        let offset = offset.min(self.len);
+        let before = self.before.partition_point(|&p| p < offset);
+        let after = self.after.len() - self.after.partition_point(|&d| d <= self.len - offset);
+
+        before + after
+    }
+
+    // offset of the k-th (0-based) newline
+    fn newline(&self, k: usize) -> Option<usize> {
+        if k < self.before.len() {
+            Some(self.before[k])
+        } else {
+            let j = k - self.before.len();
+            if j < self.after.len() {
+                Some(self.len - self.after[self.after.len() - 1 - j])
+            } else {
+                None
+            }
+        }
+    }
+
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        if line == 0 {
+            Some(0)
+        } else {
+            self.newline(line - 1).map(|p| p + 1)
+        }
+    }
+
+    // offset of the newline ending `line`, or the end of the text for the last line
+    pub fn line_end(&self, line: usize) -> usize {
+        self.newline(line).unwrap_or(self.len)
+    }
+
+    // (byte column, line)
+    pub fn point(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_of(offset);
+        let start = self.line_start(line).unwrap_or(0);
+
+        (offset.min(self.len) - start, line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn newlines(idx: &LineIndex) -> Vec<usize> {
+        (0..idx.line_count() - 1)
+            .map(|k| idx.newline(k).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_line_index_new_1() {
+        let idx = LineIndex::new(b"ab\ncd\n\ne");
+        assert_eq!(idx.line_count(), 4);
+        assert_eq!(newlines(&idx), vec![2, 5, 6]);
+        assert_eq!(idx.line_start(1), Some(3));
+        assert_eq!(idx.line_start(3), Some(7));
+        assert_eq!(idx.line_start(4), None);
+        assert_eq!(idx.line_end(0), 2);
+        assert_eq!(idx.line_end(3), 8);
+    }
+
+    #[test]
+    fn test_line_index_new_2() {
+        let idx = LineIndex::new(b"");
+        assert_eq!(idx.line_count(), 1);
+        assert_eq!(idx.point(0), (0, 0));
+        assert_eq!(idx.line_end(0), 0);
+    }
+
+    #[test]
+    fn test_line_index_point_1() {
+        let idx = LineIndex::new(b"ab\ncd\n\ne");
+        assert_eq!(idx.point(0), (0, 0));
+        assert_eq!(idx.point(2), (2, 0));
+        assert_eq!(idx.point(3), (0, 1));
+        assert_eq!(idx.point(6), (0, 2));
+        assert_eq!(idx.point(8), (1, 3));
+    }
+
+    #[test]
+    fn test_line_index_insert_1() {
+        let mut idx = LineIndex::new(b"ab\ncd");
+        idx.insert(4, b"\nx\n");
+        assert_eq!(newlines(&idx), vec![2, 4, 6]);
+
+        idx.insert(0, b"\n");
+        assert_eq!(newlines(&idx), vec![0, 3, 5, 7]);
+        assert_eq!(idx.point(9), (1, 4));
+    }
+
+    #[test]
+    fn test_line_index_delete_1() {
+        let mut idx = LineIndex::new(b"ab\ncd\n\ne");
+        idx.delete(1..6);
+        assert_eq!(newlines(&idx), vec![1]);
+        assert_eq!(idx.point(3), (1, 1));
+
+        idx.delete(0..1);
+        assert_eq!(newlines(&idx), vec![0]);
+    }
+
+    #[test]
+    fn test_line_index_edits_match_rescan() {
+        let mut text = b"hello\nworld\n\nfoo bar\nbaz".to_vec();
+        let mut idx = LineIndex::new(&text);
+
+        let edits: [(usize, &[u8], usize); 5] = [
+            (3, b"\n\n", 0),
+            (20, b"", 4),
+            (0, b"x\ny", 2),
+            (10, b"\n", 1),
+            (5, b"", 7),
+        ];
+
+        for (at, ins, del) in edits {
+            idx.delete(at..at + del);
+            text.drain(at..at + del);
+            idx.insert(at, ins);
+            text.splice(at..at, ins.iter().copied());
+
+            let expected = LineIndex::new(&text);
+            assert_eq!(newlines(&idx), newlines(&expected));
+            for i in 0..=text.len() {
+                assert_eq!(idx.point(i), expected.point(i));
+            }
+        }
+    }
+}
@@ -1,70 +1,82 @@
 mod buffer;
+mod clipboard;
+mod config;
 mod editor;
+mod encoding;
+#[cfg(test)]
+mod fuzz;
+mod kill_ring;
+mod line_ending;
+mod line_index;
+mod mark;
 mod message;
+mod piece_table;
 mod representer;
+mod snapshot;
+mod storage;
 mod undo;
+mod undo_file;
 mod view;
+mod width;
+mod word;
+mod wrap;
 
-use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::event::{
+    self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyModifiers,
+};
+use crossterm::execute;
 use std::env;
 use std::fs::OpenOptions;
 use std::io::{self, BufReader, Read};
+use std::process;
+use std::time::Duration;
 
+use crate::config::Config;
 use crate::editor::Editor;
 use crate::message::Message;
 use crate::view::View;
 
+// short enough for search matches to show up as the term is typed
+const JOB_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
 fn main() -> io::Result<()> {
-    let mut terminal = ratatui::init();
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        // TODO: Do better
-        panic!("must accept file path");
-    }
+    let paths: Vec<String> = env::args().skip(1).collect();
+    let Some((first, others)) = paths.split_first() else {
+        eprintln!("usage: kame <path-to-file>...");
+        process::exit(2);
+    };
 
-    let path = &args[1];
-    let file = OpenOptions::new()
-        .write(true)
-        .read(true)
-        .create(true)
-        .open(path)?;
-    let mut reader = BufReader::new(&file);
+    // the first file is shown, the others are a buffer switch away
+    let mut editor = Editor::open(&read(first)?, first.clone(), Config::load());
+    for path in others {
+        editor.open_buffer(&read(path)?, path.clone());
+    }
 
-    let mut buffer = String::new();
+    let mut terminal = ratatui::init();
+    // pastes come as a whole instead of as typed keys
     // TODO: restore terminal state before returning early (do this for all `?`)
-    reader.read_to_string(&mut buffer)?;
-    let mut editor = Editor::new(buffer, String::from(path));
+    execute!(io::stdout(), EnableBracketedPaste)?;
     let size = terminal.size().unwrap();
     let view = View::new(size);
+    let mut prefix = None;
+
+    let mut redraw = true;
 
     loop {
-        terminal.draw(|frame| view.render(frame, &mut editor))?;
+        if redraw {
+            terminal.draw(|frame| view.render(frame, &mut editor))?;
+        }
+
+        // wake up now and then to pick up the result of background jobs
+        if !event::poll(JOB_POLL_INTERVAL)? {
+            redraw = editor.poll_jobs();
+            continue;
+        }
 
+        redraw = true;
         let message = match event::read()? {
-            Event::Key(key) if key.modifiers == KeyModifiers::CONTROL => match key.code {
-                KeyCode::Char('q') => Message::Quit,
-                KeyCode::Char('u') => Message::Undo,
-                KeyCode::Char('g') => Message::Redo,
-                KeyCode::Char('d') => Message::DeleteUnderCursor,
-                KeyCode::Char('k') => Message::CutToEndOfLine,
-                KeyCode::Char('f') => Message::ForwardOneChar,
-                KeyCode::Char('b') => Message::BackwardOneChar,
-                KeyCode::Char('p') => Message::JumpToPreviousLine,
-                KeyCode::Char('n') => Message::JumpToNextLine,
-                KeyCode::Char('e') => Message::JumpToEndOfLine,
-                KeyCode::Char('a') => Message::JumpToBeginningOfLine,
-                KeyCode::Char('s') => Message::Save,
-                KeyCode::Char('h') => Message::UserManual,
-                KeyCode::Char('r') => Message::Search,
-                _ => Message::Noop,
-            },
-            Event::Key(key) => match key.code {
-                KeyCode::Backspace => Message::DeleteBeforeCursor,
-                KeyCode::Enter => Message::InsertNewLine,
-                KeyCode::Tab => Message::InsertTab,
-                KeyCode::Char(c) => Message::Insert(c),
-                _ => Message::Noop,
-            },
+            Event::Key(key) => translate(&mut prefix, key),
+            Event::Paste(s) => Message::Paste(s),
             _ => Message::Noop,
         };
 
@@ -75,7 +87,129 @@ fn main() -> io::Result<()> {
         editor.update(message);
     }
 
+    editor.close();
+    execute!(io::stdout(), DisableBracketedPaste)?;
     ratatui::restore();
 
     Ok(())
 }
+
+// the contents of the file at `path`, created empty when it does not exist
+fn read(path: &str) -> io::Result<Vec<u8>> {
+    let file = OpenOptions::new()
+        .write(true)
+        .read(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let mut reader = BufReader::new(&file);
+
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+// keys that wait for more keys to complete a command
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Prefix {
+    CtrlX,
+    CtrlXReturn,
+    CtrlXX,
+    AltG,
+}
+
+fn translate(prefix: &mut Option<Prefix>, key: KeyEvent) -> Message {
+    match prefix.take() {
+        Some(Prefix::CtrlX) => match key.code {
+            KeyCode::Enter => {
+                *prefix = Some(Prefix::CtrlXReturn);
+                Message::Noop
+            }
+            KeyCode::Char('u') => Message::UndoTree,
+            KeyCode::Char('[') => Message::PreviousBranch,
+            KeyCode::Char(']') => Message::NextBranch,
+            KeyCode::Char('t') => Message::UndoToTime,
+            KeyCode::Char('b') if key.modifiers == KeyModifiers::CONTROL => Message::BufferList,
+            KeyCode::Char('b') => Message::SwitchBuffer,
+            KeyCode::Char('k') => Message::KillBuffer,
+            KeyCode::Backspace => Message::DeleteCodePointBeforeCursor,
+            KeyCode::Char('x') => {
+                *prefix = Some(Prefix::CtrlXX);
+                Message::Noop
+            }
+            _ => Message::Noop,
+        },
+        Some(Prefix::CtrlXX) => match key.code {
+            KeyCode::Char('t') => Message::ToggleWrap,
+            _ => Message::Noop,
+        },
+        Some(Prefix::CtrlXReturn) => match key.code {
+            KeyCode::Char('f') => Message::SetEncoding,
+            KeyCode::Char('l') => Message::SetLineEnding,
+            _ => Message::Noop,
+        },
+        Some(Prefix::AltG) => match key.code {
+            KeyCode::Char('g') => Message::GotoLine,
+            _ => Message::Noop,
+        },
+        None if key.modifiers == KeyModifiers::CONTROL => match key.code {
+            KeyCode::Char('q') => Message::Quit,
+            KeyCode::Char('u') => Message::Undo,
+            KeyCode::Char('g') => Message::Redo,
+            KeyCode::Char('d') => Message::DeleteUnderCursor,
+            KeyCode::Char('k') => Message::CutToEndOfLine,
+            KeyCode::Char('y') => Message::Yank,
+            KeyCode::Char(' ') | KeyCode::Char('@') => Message::SetMark,
+            KeyCode::Char('w') => Message::KillRegion,
+            KeyCode::Char('f') => Message::ForwardOneChar,
+            KeyCode::Char('b') => Message::BackwardOneChar,
+            KeyCode::Char('p') => Message::JumpToPreviousLine,
+            KeyCode::Char('n') => Message::JumpToNextLine,
+            KeyCode::Char('e') => Message::JumpToEndOfLine,
+            KeyCode::Char('a') => Message::JumpToBeginningOfLine,
+            KeyCode::Char('s') => Message::Save,
+            KeyCode::Char('h') => Message::UserManual,
+            KeyCode::Char('r') => Message::Search,
+            KeyCode::Char('v') => Message::NextPage,
+            KeyCode::Char('l') => Message::Recenter,
+            KeyCode::Char('x') => {
+                *prefix = Some(Prefix::CtrlX);
+                Message::Noop
+            }
+            _ => Message::Noop,
+        },
+        // M-< and M-> are typed with shift
+        None if key.modifiers.difference(KeyModifiers::SHIFT) == KeyModifiers::ALT => {
+            match key.code {
+                KeyCode::Char('y') => Message::YankPop,
+                KeyCode::Char('w') => Message::CopyRegion,
+                KeyCode::Char('f') => Message::ForwardWord,
+                KeyCode::Char('b') => Message::BackwardWord,
+                KeyCode::Char('d') => Message::KillWord,
+                KeyCode::Backspace => Message::BackwardKillWord,
+                KeyCode::Char('t') => Message::TransposeWords,
+                KeyCode::Char('u') => Message::UpcaseWord,
+                KeyCode::Char('l') => Message::DowncaseWord,
+                KeyCode::Char('c') => Message::CapitalizeWord,
+                KeyCode::Char('v') => Message::PreviousPage,
+                KeyCode::Char('<') => Message::BeginningOfBuffer,
+                KeyCode::Char('>') => Message::EndOfBuffer,
+                KeyCode::Char('g') => {
+                    *prefix = Some(Prefix::AltG);
+                    Message::Noop
+                }
+                _ => Message::Noop,
+            }
+        }
+        None => match key.code {
+            KeyCode::Backspace => Message::DeleteBeforeCursor,
+            KeyCode::Enter => Message::InsertNewLine,
+            KeyCode::Tab => Message::InsertTab,
+            KeyCode::PageDown => Message::NextPage,
+            KeyCode::PageUp => Message::PreviousPage,
+            KeyCode::Char(c) => Message::Insert(c),
+            _ => Message::Noop,
+        },
+    }
+}
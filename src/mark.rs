@@ -0,0 +1,130 @@
+use std::ops::Range;
+
+// which side of an insertion made exactly at the mark the mark ends up on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gravity {
+    // stays before the inserted text
+    Left,
+    // moves after the inserted text
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MarkId(usize);
+
+#[derive(Debug, Clone, Copy)]
+struct Mark {
+    offset: usize,
+    gravity: Gravity,
+}
+
+// Byte offsets that follow the text they point at. Every edit made to the storage
+// goes through `insert` and `delete`, which shift the marks after the edited spot.
+#[derive(Debug, Default)]
+pub struct Marks {
+    slots: Vec<Option<Mark>>,
+    free: Vec<usize>,
+}
+
+impl Marks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn create(&mut self, offset: usize, gravity: Gravity) -> MarkId {
+        let mark = Some(Mark { offset, gravity });
+
+        match self.free.pop() {
+            Some(i) => {
+                self.slots[i] = mark;
+                MarkId(i)
+            }
+            None => {
+                self.slots.push(mark);
+                MarkId(self.slots.len() - 1)
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: MarkId) {
+        if let Some(slot) = self.slots.get_mut(id.0)
+            && slot.take().is_some()
+        {
+            self.free.push(id.0);
+        }
+    }
+
+    pub fn get(&self, id: MarkId) -> Option<usize> {
+        self.slots.get(id.0).copied().flatten().map(|m| m.offset)
+    }
+
+    // `n` bytes were inserted at `at`
+    pub fn insert(&mut self, at: usize, n: usize) {
+        for mark in self.slots.iter_mut().flatten() {
+            if mark.offset > at || (mark.offset == at && mark.gravity == Gravity::Right) {
+                mark.offset += n;
+            }
+        }
+    }
+
+    // marks inside the deleted range collapse to its start
+    pub fn delete(&mut self, range: Range<usize>) {
+        for mark in self.slots.iter_mut().flatten() {
+            if mark.offset >= range.end {
+                mark.offset -= range.len();
+            } else if mark.offset > range.start {
+                mark.offset = range.start;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marks_insert_1() {
+        let mut marks = Marks::new();
+        let a = marks.create(2, Gravity::Left);
+        let b = marks.create(2, Gravity::Right);
+        let c = marks.create(1, Gravity::Right);
+        let d = marks.create(5, Gravity::Left);
+
+        marks.insert(2, 3);
+        assert_eq!(marks.get(a), Some(2));
+        assert_eq!(marks.get(b), Some(5));
+        assert_eq!(marks.get(c), Some(1));
+        assert_eq!(marks.get(d), Some(8));
+    }
+
+    #[test]
+    fn test_marks_delete_1() {
+        let mut marks = Marks::new();
+        let a = marks.create(1, Gravity::Left);
+        let b = marks.create(3, Gravity::Right);
+        let c = marks.create(5, Gravity::Left);
+        let d = marks.create(9, Gravity::Left);
+
+        marks.delete(2..5);
+        assert_eq!(marks.get(a), Some(1));
+        assert_eq!(marks.get(b), Some(2));
+        assert_eq!(marks.get(c), Some(2));
+        assert_eq!(marks.get(d), Some(6));
+    }
+
+    #[test]
+    fn test_marks_remove_1() {
+        let mut marks = Marks::new();
+        let a = marks.create(1, Gravity::Left);
+        marks.remove(a);
+        assert_eq!(marks.get(a), None);
+
+        marks.remove(a);
+        let b = marks.create(4, Gravity::Left);
+        let c = marks.create(6, Gravity::Left);
+        assert_eq!(b, a);
+        assert_ne!(c, a);
+        assert_eq!(marks.get(b), Some(4));
+    }
+}
@@ -5,18 +5,50 @@ pub enum Message {
     Save,
     UserManual,
     Search,
+    SetEncoding,
+    SetLineEnding,
     DeleteUnderCursor,
     DeleteBeforeCursor,
+    DeleteCodePointBeforeCursor,
     CutToEndOfLine,
+    KillWord,
+    BackwardKillWord,
+    TransposeWords,
+    UpcaseWord,
+    DowncaseWord,
+    CapitalizeWord,
+    Yank,
+    SetMark,
+    KillRegion,
+    CopyRegion,
+    YankPop,
     Insert(char),
+    // text pasted by the terminal
+    Paste(String),
     InsertNewLine,
     InsertTab,
     Undo,
     Redo,
+    NextBranch,
+    PreviousBranch,
+    UndoToTime,
+    UndoTree,
     ForwardOneChar,
     BackwardOneChar,
+    ForwardWord,
+    BackwardWord,
     JumpToPreviousLine,
     JumpToNextLine,
     JumpToEndOfLine,
     JumpToBeginningOfLine,
+    NextPage,
+    PreviousPage,
+    Recenter,
+    BeginningOfBuffer,
+    EndOfBuffer,
+    GotoLine,
+    ToggleWrap,
+    SwitchBuffer,
+    KillBuffer,
+    BufferList,
 }
@@ -0,0 +1,391 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::line_index::LineIndex;
+use crate::mark::Marks;
+use crate::snapshot::{Shared, Snapshot};
+use crate::storage::TextStorage;
+#[cfg(test)]
+use crate::storage::utf8_char_len;
+
+// The text is never modified in place. It is described by a list of pieces, each
+// one pointing into either the original (read-only) text or the append-only
+// `add` buffer:
+//
+// original: [hello world]          add: [big ]
+// pieces:   (original, 0, 6) -> (add, 0, 4) -> (original, 6, 5)
+// text:     "hello big world"
+//
+// Loading a file is free, and appending at the end of the last added piece only
+// extends that piece.
+//
+// Bytes are never changed once written, so a snapshot is just the piece list with
+// the buffers shared. The `add` buffer is a list of blocks: while a snapshot holds
+// the last one, new text goes to a fresh block instead.
+#[derive(Debug)]
+pub struct PieceTable {
+    original: Shared,
+    add: Vec<Shared>,
+    pieces: Vec<Piece>,
+    cursor: usize,
+    len: usize,
+    lines: LineIndex,
+    marks: Marks,
+    version: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    Original,
+    // index of the block in `add`
+    Add(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Piece {
+    source: Source,
+    start: usize,
+    len: usize,
+    // where the piece starts in the text, for `locate` to binary search
+    at: usize,
+}
+
+#[derive(Debug)]
+pub struct PieceTableIter<'a> {
+    table: &'a PieceTable,
+    piece: usize,
+    offset: usize,
+}
+
+impl<'a> Iterator for PieceTableIter<'a> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let piece = self.table.pieces.get(self.piece)?;
+        let b = self.table.bytes_of(piece)[self.offset];
+
+        self.offset += 1;
+        if self.offset == piece.len {
+            self.piece += 1;
+            self.offset = 0;
+        }
+
+        Some(b)
+    }
+}
+
+impl PieceTable {
+    pub fn init(s: String) -> Self {
+        let original = Arc::new(s.into_bytes());
+        let len = original.len();
+        let lines = LineIndex::new(&original);
+        let pieces = if len == 0 {
+            Vec::new()
+        } else {
+            vec![Piece {
+                source: Source::Original,
+                start: 0,
+                len,
+                at: 0,
+            }]
+        };
+
+        Self {
+            original,
+            add: Vec::new(),
+            pieces,
+            cursor: 0,
+            len,
+            lines,
+            marks: Marks::new(),
+            version: 0,
+        }
+    }
+
+    fn source_of(&self, piece: &Piece) -> &Shared {
+        match piece.source {
+            Source::Original => &self.original,
+            Source::Add(block) => &self.add[block],
+        }
+    }
+
+    fn bytes_of(&self, piece: &Piece) -> &[u8] {
+        &self.source_of(piece)[piece.start..piece.start + piece.len]
+    }
+
+    // (piece index, offset inside the piece) of the byte offset `i`
+    // an offset at the end of the text maps to (pieces.len(), 0)
+    fn locate(&self, i: usize) -> (usize, usize) {
+        let j = self.pieces.partition_point(|p| p.at + p.len <= i);
+        match self.pieces.get(j) {
+            Some(piece) => (j, i - piece.at),
+            None => (self.pieces.len(), 0),
+        }
+    }
+
+    // recomputes where the pieces from `from` on start, after pieces before them
+    // changed length or were added or removed
+    fn reindex(&mut self, from: usize) {
+        let mut at = match from {
+            0 => 0,
+            _ => self.pieces[from - 1].at + self.pieces[from - 1].len,
+        };
+        for piece in &mut self.pieces[from..] {
+            piece.at = at;
+            at += piece.len;
+        }
+    }
+
+    // makes sure a piece starts at the byte offset `i` and returns its index
+    fn split_at(&mut self, i: usize) -> usize {
+        let (j, offset) = self.locate(i);
+        if offset == 0 {
+            return j;
+        }
+
+        let piece = self.pieces[j];
+        self.pieces[j].len = offset;
+        self.pieces.insert(
+            j + 1,
+            Piece {
+                source: piece.source,
+                start: piece.start + offset,
+                len: piece.len - offset,
+                at: piece.at + offset,
+            },
+        );
+
+        j + 1
+    }
+
+    fn insert_bytes(&mut self, i: usize, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+
+        if self.add.last_mut().and_then(Arc::get_mut).is_none() {
+            self.add.push(Arc::new(Vec::new()));
+        }
+        let block = self.add.len() - 1;
+        let add = Arc::get_mut(&mut self.add[block]).expect("shared add block");
+        let start = add.len();
+        add.extend_from_slice(bytes);
+        self.lines.insert(i, bytes);
+        self.marks.insert(i, bytes.len());
+        self.len += bytes.len();
+        self.version += 1;
+
+        let j = self.split_at(i);
+
+        // typing keeps extending the same piece
+        if j > 0 {
+            let prev = &mut self.pieces[j - 1];
+            if prev.source == Source::Add(block) && prev.start + prev.len == start {
+                prev.len += bytes.len();
+                self.reindex(j);
+                return;
+            }
+        }
+
+        self.pieces.insert(
+            j,
+            Piece {
+                source: Source::Add(block),
+                start,
+                len: bytes.len(),
+                at: i,
+            },
+        );
+        self.reindex(j + 1);
+    }
+}
+
+impl TextStorage for PieceTable {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    fn jump(&mut self, n: usize) {
+        self.cursor = n.min(self.len);
+    }
+
+    #[cfg(test)]
+    fn move_ptr_forward(&mut self) {
+        if let Some(b) = self.byte_at(self.cursor) {
+            self.cursor += utf8_char_len(b);
+        }
+    }
+
+    #[cfg(test)]
+    fn move_ptr_backward(&mut self) {
+        self.cursor = self.floor_char_boundary(self.cursor.saturating_sub(1));
+    }
+
+    fn insert_str(&mut self, s: &str) {
+        self.insert_bytes(self.cursor, s.as_bytes());
+        self.cursor += s.len();
+    }
+
+    fn delete_range(&mut self, range: Range<usize>) -> Vec<u8> {
+        self.cursor = range.start;
+        if range.is_empty() {
+            return Vec::new();
+        }
+
+        let deleted = self.slice(range.clone());
+        let first = self.split_at(range.start);
+        let last = self.split_at(range.end);
+        self.pieces.drain(first..last);
+        self.reindex(first);
+        self.lines.delete(range.clone());
+        self.marks.delete(range.clone());
+        self.len -= range.len();
+        self.version += 1;
+
+        deleted
+    }
+
+    fn iter_from(&self, i: usize) -> Box<dyn Iterator<Item = u8> + '_> {
+        let (piece, offset) = self.locate(i);
+
+        Box::new(PieceTableIter {
+            table: self,
+            piece,
+            offset,
+        })
+    }
+
+    // a single lookup, char and grapheme boundaries are looked for byte by byte
+    fn byte_at(&self, i: usize) -> Option<u8> {
+        let (j, offset) = self.locate(i);
+        let piece = self.pieces.get(j)?;
+
+        Some(self.bytes_of(piece)[offset])
+    }
+
+    fn lines(&self) -> &LineIndex {
+        &self.lines
+    }
+
+    fn marks(&self) -> &Marks {
+        &self.marks
+    }
+
+    fn marks_mut(&mut self) -> &mut Marks {
+        &mut self.marks
+    }
+
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn snapshot(&mut self) -> Snapshot {
+        let slices = self
+            .pieces
+            .iter()
+            .map(|p| (self.source_of(p).clone(), p.start..p.start + p.len))
+            .collect();
+
+        Snapshot::new(slices, self.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_piece_table_init_1() {
+        let table = PieceTable::init(String::from("hello"));
+        assert_eq!(table.pieces.len(), 1);
+        assert!(table.add.is_empty());
+
+        let table = PieceTable::init(String::from(""));
+        assert!(table.pieces.is_empty());
+    }
+
+    #[test]
+    fn test_piece_table_insert_1() {
+        let mut table = PieceTable::init(String::from("hello world"));
+        table.jump(6);
+        table.insert_str("big ");
+
+        assert_eq!(table.to_string(), "hello big world");
+        assert_eq!(table.pieces.len(), 3);
+        assert_eq!(*table.add[0], b"big ");
+    }
+
+    #[test]
+    fn test_piece_table_append_1() {
+        let mut table = PieceTable::init(String::from("log\n"));
+        table.jump(4);
+        for _ in 0..100 {
+            table.insert('x');
+        }
+
+        assert_eq!(table.pieces.len(), 2);
+        assert_eq!(*table.original, b"log\n");
+    }
+
+    #[test]
+    fn test_piece_table_remove_1() {
+        let mut table = PieceTable::init(String::from("hello world"));
+        table.jump(5);
+        table.insert(',');
+
+        assert_eq!(table.delete_range(3..8), b"lo, w");
+        assert_eq!(table.to_string(), "helorld");
+        assert_eq!(table.pieces.len(), 2);
+    }
+
+    #[test]
+    fn test_piece_table_locate_1() {
+        let mut table = PieceTable::init(String::from("0123456789"));
+        for i in [8, 6, 4, 2] {
+            table.jump(i);
+            table.insert('x');
+        }
+        table.delete_range(3..5);
+
+        assert_eq!(table.to_string(), "01xx45x67x89");
+        assert_eq!(table.pieces.len(), 8);
+        assert_eq!(table.locate(0), (0, 0));
+        assert_eq!(table.locate(1), (0, 1));
+        assert_eq!(table.locate(3), (2, 0));
+        assert_eq!(table.locate(5), (3, 1));
+        assert_eq!(table.locate(11), (7, 1));
+        assert_eq!(table.locate(12), (8, 0));
+        assert_eq!(table.byte_at(9), Some(b'x'));
+        assert_eq!(table.byte_at(3), Some(b'x'));
+        assert_eq!(table.byte_at(12), None);
+    }
+
+    #[test]
+    fn test_piece_table_snapshot_1() {
+        let mut table = PieceTable::init(String::from("hello world"));
+        table.jump(5);
+        table.insert(',');
+        let before = table.snapshot();
+
+        // the snapshot keeps the add block, typing goes on in a new one
+        table.insert(' ');
+        table.insert('x');
+        assert_eq!(table.add.len(), 2);
+        assert_eq!(table.pieces.len(), 4);
+        assert_eq!(
+            before.chunks().nth(1).map(<[u8]>::as_ptr),
+            Some(table.add[0].as_ptr())
+        );
+        assert_eq!(before.text(), "hello, world");
+        assert_eq!(table.snapshot().text(), "hello, x world");
+
+        // without a snapshot around, the last block is extended in place
+        drop(before);
+        table.insert('y');
+        assert_eq!(table.add.len(), 2);
+    }
+}
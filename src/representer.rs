@@ -1,15 +1,24 @@
 use ratatui::style::{Color, Style};
 use ratatui::text::{Line, Span, Text};
 
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::width;
+
 #[derive(Debug)]
 pub struct Representer {
     elements: Vec<Element>,
+    // the columns shown, lines are cut to them
+    left: usize,
+    width: usize,
 }
 
 impl Representer {
-    pub fn new() -> Self {
+    pub fn new(left: usize, width: usize) -> Self {
         Self {
             elements: Vec::with_capacity(100),
+            left,
+            width,
         }
     }
 
@@ -19,38 +28,87 @@ impl Representer {
 
     pub fn decorate(&self) -> Text<'_> {
         let mut lines = Vec::new();
-        let mut line = Line::raw("");
+        let mut line = Vec::new();
 
         let normal_txt = Style::default().fg(Color::Black).bg(Color::White);
         let selected_txt = Style::default().fg(Color::Red).bg(Color::Gray);
+        let region_txt = Style::default().fg(Color::Black).bg(Color::LightCyan);
 
+        let mut col = 0;
         for e in &self.elements {
             let s = unsafe { str::from_utf8_unchecked(&e.val) };
+            let s = width::expand_tabs(s, &mut col);
 
-            match e.t {
-                ElementType::Normal => {
-                    line.push_span(Span::styled(s, normal_txt));
-                }
-                ElementType::IncrementalSearch(false) => {
-                    line.push_span(Span::styled(s, selected_txt));
-                }
-                ElementType::IncrementalSearch(true) => {
-                    line.push_span(Span::styled(s, selected_txt.bg(Color::Black)));
-                }
-            }
+            let style = match e.t {
+                ElementType::Normal => normal_txt,
+                ElementType::IncrementalSearch(false) => selected_txt,
+                ElementType::IncrementalSearch(true) => selected_txt.bg(Color::Black),
+                ElementType::Region => region_txt,
+            };
+            line.push(Span::styled(s, style));
 
             if *e.val.last().unwrap() == b'\n' {
-                lines.push(line);
-                line = Line::raw("");
+                lines.push(self.clip(std::mem::take(&mut line), normal_txt));
             }
         }
 
+        let line = self.clip(line, normal_txt);
         if line.width() > 0 {
             lines.push(line);
         }
 
         Text::from(lines)
     }
+
+    // the part of a line between the left and right edges, with a `$` in the first or
+    // last column when there is text hidden past it
+    // a wide char cut by an edge leaves blanks
+    fn clip<'a>(&self, spans: Vec<Span<'a>>, indicator: Style) -> Line<'a> {
+        let text_width = spans.iter().fold(0, |col, s| {
+            width::advance(s.content.trim_end_matches('\n'), col)
+        });
+        if self.left == 0 && text_width <= self.width {
+            return Line::from(spans);
+        }
+
+        let hidden_left = self.left > 0 && text_width > 0;
+        let hidden_right = text_width > self.left + self.width;
+        let start = self.left + hidden_left as usize;
+        let end = (self.left + self.width).saturating_sub(hidden_right as usize);
+
+        let mut line = Line::default();
+        if hidden_left {
+            line.push_span(Span::styled("$", indicator));
+        }
+
+        let mut col = 0;
+        for span in spans {
+            let mut shown = String::new();
+            for g in span.content.trim_end_matches('\n').graphemes(true) {
+                let w = width::grapheme_width(g, col);
+                if col >= start && col + w <= end {
+                    shown.push_str(g);
+                } else if col.max(start) < (col + w).min(end) {
+                    shown.extend(std::iter::repeat_n(
+                        ' ',
+                        (col + w).min(end) - col.max(start),
+                    ));
+                }
+
+                col += w;
+            }
+
+            if !shown.is_empty() {
+                line.push_span(Span::styled(shown, span.style));
+            }
+        }
+
+        if hidden_right {
+            line.push_span(Span::styled("$", indicator));
+        }
+
+        line
+    }
 }
 
 #[derive(Debug)]
@@ -59,11 +117,13 @@ pub struct Element {
     pub t: ElementType,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ElementType {
     Normal,
     // true means element is currently at the cursor's position
     IncrementalSearch(bool),
+    // between the mark and the cursor
+    Region,
 }
 
 impl Element {
@@ -81,6 +141,21 @@ impl Element {
         }
     }
 
+    pub fn region_type() -> Self {
+        Self {
+            val: Vec::new(),
+            t: ElementType::Region,
+        }
+    }
+
+    // an empty element that continues this one on the next row
+    pub fn same_type(&self) -> Self {
+        Self {
+            val: Vec::new(),
+            t: self.t,
+        }
+    }
+
     pub fn push(&mut self, v: u8) {
         self.val.push(v);
     }
@@ -0,0 +1,175 @@
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::Arc;
+
+// bytes that a storage no longer changes in place, shared with its snapshots
+pub type Shared = Arc<Vec<u8>>;
+
+// A read-only view of the text as it was at some version of the storage.
+// It is a list of slices of the storage's own shared bytes rather than a copy of
+// the text, so taking one is cheap and clones share the same list. It can be handed
+// to another thread (save, search) while the user keeps editing the live buffer.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    slices: Arc<[(Shared, Range<usize>)]>,
+    version: u64,
+}
+
+impl Snapshot {
+    pub fn new(slices: Vec<(Shared, Range<usize>)>, version: u64) -> Self {
+        Self {
+            slices: slices.into(),
+            version,
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    // the text as a sequence of contiguous slices, in order
+    // a slice may end in the middle of a utf8 char
+    pub fn chunks(&self) -> impl Iterator<Item = &[u8]> {
+        self.slices
+            .iter()
+            .map(|(bytes, range)| &bytes[range.clone()])
+    }
+
+    // the whole text, only copied when it is made of more than one slice
+    pub fn text(&self) -> Cow<'_, str> {
+        match &*self.slices {
+            [] => Cow::Borrowed(""),
+            [(bytes, range)] => {
+                Cow::Borrowed(str::from_utf8(&bytes[range.clone()]).expect("corrupted utf8"))
+            }
+            _ => {
+                let bytes = self.chunks().flatten().copied().collect();
+                // the storage only ever holds valid utf8
+                Cow::Owned(String::from_utf8(bytes).expect("corrupted utf8"))
+            }
+        }
+    }
+
+    // the ranges of `term` in `scope` that do not overlap, like `str::match_indices`
+    // each slice is searched in place, only the bytes around the end of a slice that
+    // could start a match spanning two of them are copied
+    pub fn find(&self, term: &str, scope: Range<usize>) -> Vec<Range<usize>> {
+        let term = term.as_bytes();
+        let n = term.len();
+        let mut found = Vec::new();
+        if n == 0 {
+            return found;
+        }
+
+        // the bytes at the end of the text so far that may start a match, from `done` on
+        let mut carry: Vec<u8> = Vec::new();
+        let mut done = scope.start;
+        let mut pos = 0;
+        for chunk in self.chunks() {
+            let range = pos.max(scope.start)..(pos + chunk.len()).min(scope.end);
+            let offset = pos;
+            pos += chunk.len();
+            if range.is_empty() {
+                continue;
+            }
+            let part = &chunk[range.start - offset..range.end - offset];
+
+            // matches starting in `carry` and ending in `part`
+            if !carry.is_empty() {
+                let mut joint = carry.clone();
+                joint.extend_from_slice(&part[..part.len().min(n - 1)]);
+                let base = range.start - carry.len();
+                let mut i = 0;
+                while let Some(j) = position(&joint[i..], term) {
+                    if i + j >= carry.len() {
+                        break;
+                    }
+                    found.push(base + i + j..base + i + j + n);
+                    i += j + n;
+                    done = base + i;
+                }
+            }
+
+            let mut i = done.max(range.start) - range.start;
+            while let Some(j) = position(&part[i..], term) {
+                found.push(range.start + i + j..range.start + i + j + n);
+                i += j + n;
+                done = range.start + i;
+            }
+
+            let from = done.max(range.end.saturating_sub(n - 1));
+            let mut next = Vec::with_capacity(n - 1);
+            if from < range.start {
+                next.extend_from_slice(&carry[carry.len() - (range.start - from)..]);
+            }
+            next.extend_from_slice(&part[from.max(range.start) - range.start..]);
+            carry = next;
+        }
+
+        found
+    }
+}
+
+fn position(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn test_snapshot_send_1() {
+        let bytes = Arc::new(b"hello world".to_vec());
+        let snapshot = Snapshot::new(vec![(bytes.clone(), 0..6), (bytes, 6..11)], 3);
+        let other = snapshot.clone();
+        assert!(Arc::ptr_eq(&snapshot.slices, &other.slices));
+
+        let n = thread::spawn(move || other.text().matches('o').count())
+            .join()
+            .unwrap();
+        assert_eq!(n, 2);
+        assert_eq!(snapshot.version(), 3);
+    }
+
+    #[test]
+    fn test_snapshot_text_1() {
+        let bytes = Arc::new("chào".as_bytes().to_vec());
+        let whole = Snapshot::new(vec![(bytes.clone(), 0..5)], 0);
+        assert!(matches!(whole.text(), Cow::Borrowed("chào")));
+
+        // the slices split the à in two
+        let split = Snapshot::new(vec![(bytes.clone(), 0..3), (bytes, 3..5)], 0);
+        assert_eq!(split.chunks().count(), 2);
+        assert_eq!(split.text(), "chào");
+        assert_eq!(Snapshot::new(Vec::new(), 0).text(), "");
+    }
+
+    #[test]
+    fn test_snapshot_find_1() {
+        let text = "aaa chào aaaa chào";
+        let bytes = Arc::new(text.as_bytes().to_vec());
+        // every way of cutting the text in three slices
+        for i in 0..text.len() {
+            for j in i..text.len() {
+                let slices = vec![
+                    (bytes.clone(), 0..i),
+                    (bytes.clone(), i..j),
+                    (bytes.clone(), j..text.len()),
+                ];
+                let snapshot = Snapshot::new(slices, 0);
+                for term in ["a", "aa", "aaa", "chào", "à", "o a", "x"] {
+                    for scope in [0..text.len(), 2..text.len(), 4..11, 5..5] {
+                        let expected: Vec<Range<usize>> = text[scope.clone()]
+                            .match_indices(term)
+                            .map(|(k, m)| scope.start + k..scope.start + k + m.len())
+                            .collect();
+                        assert_eq!(snapshot.find(term, scope), expected);
+                    }
+                }
+            }
+        }
+    }
+}
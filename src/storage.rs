@@ -0,0 +1,821 @@
+use std::fmt::Debug;
+use std::ops::Range;
+
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+
+use crate::buffer::Buffer;
+use crate::line_index::LineIndex;
+use crate::mark::Marks;
+use crate::piece_table::PieceTable;
+use crate::snapshot::Snapshot;
+
+// files bigger than this are loaded into a piece table: opening them does not copy
+// the text around and appending to them never moves the existing bytes
+pub const PIECE_TABLE_THRESHOLD: usize = 8 * 1024 * 1024;
+// how many bytes are handed to the grapheme segmenter at a time
+const GRAPHEME_CHUNK_LEN: usize = 64;
+
+// Everything the editor and the undo manager need from a text backend.
+// All offsets are byte offsets into the text and must be on a utf8 char boundary.
+pub trait TextStorage: Debug {
+    fn len(&self) -> usize;
+
+    // the insertion point
+    fn cursor(&self) -> usize;
+
+    fn jump(&mut self, n: usize);
+
+    // code point movement, the editor moves by grapheme cluster
+    #[cfg(test)]
+    fn move_ptr_forward(&mut self);
+
+    #[cfg(test)]
+    fn move_ptr_backward(&mut self);
+
+    // inserts `s` at the insertion point and moves the insertion point after it
+    fn insert_str(&mut self, s: &str);
+
+    // removes `range` and returns the removed bytes, the insertion point ends up at `range.start`
+    fn delete_range(&mut self, range: Range<usize>) -> Vec<u8>;
+
+    fn iter_from(&self, i: usize) -> Box<dyn Iterator<Item = u8> + '_>;
+
+    fn lines(&self) -> &LineIndex;
+
+    // offsets kept up to date by `insert_str` and `delete_range`
+    fn marks(&self) -> &Marks;
+
+    fn marks_mut(&mut self) -> &mut Marks;
+
+    // bumped by every `insert_str` and `delete_range`
+    fn version(&self) -> u64;
+
+    // the text tagged with the current version, sharing the bytes that did not change
+    // since the previous snapshot instead of copying them
+    fn snapshot(&mut self) -> Snapshot;
+
+    // the editor always goes through `insert_str`
+    #[cfg(test)]
+    fn insert(&mut self, c: char) {
+        let mut tmp = [0; 4];
+        self.insert_str(c.encode_utf8(&mut tmp));
+    }
+
+    // code point deletion, `delete_grapheme_before_ptr` deletes what the user sees as a char
+    fn delete_before_ptr(&mut self) -> Option<Vec<u8>> {
+        let i = self.cursor();
+        if i == 0 {
+            return None;
+        }
+
+        Some(self.delete_range(self.floor_char_boundary(i - 1)..i))
+    }
+
+    #[cfg(test)]
+    fn delete_after_ptr(&mut self) -> Option<Vec<u8>> {
+        let i = self.cursor();
+        let len = utf8_char_len(self.byte_at(i)?);
+
+        Some(self.delete_range(i..i + len))
+    }
+
+    // deletes the whole extended grapheme cluster before the insertion point
+    fn delete_grapheme_before_ptr(&mut self) -> Option<Vec<u8>> {
+        let i = self.cursor();
+        if i == 0 {
+            return None;
+        }
+
+        Some(self.delete_range(self.prev_grapheme_boundary(i)..i))
+    }
+
+    // deletes the whole extended grapheme cluster after the insertion point
+    fn delete_grapheme_after_ptr(&mut self) -> Option<Vec<u8>> {
+        let i = self.cursor();
+        if i == self.len() {
+            return None;
+        }
+
+        Some(self.delete_range(i..self.next_grapheme_boundary(i)))
+    }
+
+    fn revert_insert(&mut self, prev: usize, n: usize) {
+        self.delete_range(prev..prev + n);
+    }
+
+    fn revert_delete_before_ptr(&mut self, prev: usize, deleted: &[u8]) {
+        self.jump(prev);
+        self.insert_str(str::from_utf8(deleted).expect("corrupted utf8"));
+    }
+
+    fn revert_delete_after_ptr(&mut self, prev: usize, deleted: &[u8]) {
+        self.revert_delete_before_ptr(prev, deleted);
+        self.jump(prev);
+    }
+
+    fn slice(&self, range: Range<usize>) -> Vec<u8> {
+        self.iter_from(range.start).take(range.len()).collect()
+    }
+
+    fn byte_at(&self, i: usize) -> Option<u8> {
+        self.iter_from(i).next()
+    }
+
+    // the closest utf8 char boundary at or before `i`
+    fn floor_char_boundary(&self, i: usize) -> usize {
+        let mut i = i.min(self.len());
+        while i > 0
+            && let Some(b) = self.byte_at(i)
+            && b & 0xC0 == 0x80
+        {
+            i -= 1;
+        }
+
+        i
+    }
+
+    // the closest utf8 char boundary at or after `i`
+    fn ceil_char_boundary(&self, i: usize) -> usize {
+        let mut i = i.min(self.len());
+        while let Some(b) = self.byte_at(i)
+            && b & 0xC0 == 0x80
+        {
+            i += 1;
+        }
+
+        i
+    }
+
+    // (start, text) of `start..end` widened to utf8 char boundaries
+    fn str_between(&self, start: usize, end: usize) -> (usize, String) {
+        let start = self.floor_char_boundary(start);
+        let end = self.ceil_char_boundary(end);
+        let s = String::from_utf8(self.slice(start..end)).expect("corrupted utf8");
+
+        (start, s)
+    }
+
+    // the text is fed to the segmenter in small chunks, so this only looks at the
+    // clusters around `i` no matter how long the line is
+    fn next_grapheme_boundary(&self, i: usize) -> usize {
+        let len = self.len();
+        let mut cursor = GraphemeCursor::new(i, len, true);
+        let (mut start, mut chunk) = self.str_between(i, i + GRAPHEME_CHUNK_LEN);
+
+        loop {
+            match cursor.next_boundary(&chunk, start) {
+                Ok(Some(n)) => return n,
+                Ok(None) => return len,
+                Err(GraphemeIncomplete::NextChunk) => {
+                    let end = start + chunk.len();
+                    (start, chunk) = self.str_between(end, end + GRAPHEME_CHUNK_LEN);
+                }
+                Err(GraphemeIncomplete::PreContext(n)) => {
+                    let (ctx_start, ctx) =
+                        self.str_between(n.saturating_sub(GRAPHEME_CHUNK_LEN), n);
+                    cursor.provide_context(&ctx, ctx_start);
+                }
+                Err(e) => panic!("grapheme segmentation failed: {:?}", e),
+            }
+        }
+    }
+
+    fn prev_grapheme_boundary(&self, i: usize) -> usize {
+        let len = self.len();
+        let mut cursor = GraphemeCursor::new(i, len, true);
+        let (mut start, mut chunk) = self.str_between(i.saturating_sub(GRAPHEME_CHUNK_LEN), i);
+
+        loop {
+            match cursor.prev_boundary(&chunk, start) {
+                Ok(Some(n)) => return n,
+                Ok(None) => return 0,
+                Err(GraphemeIncomplete::PrevChunk) => {
+                    (start, chunk) =
+                        self.str_between(start.saturating_sub(GRAPHEME_CHUNK_LEN), start);
+                }
+                Err(GraphemeIncomplete::PreContext(n)) => {
+                    let (ctx_start, ctx) =
+                        self.str_between(n.saturating_sub(GRAPHEME_CHUNK_LEN), n);
+                    cursor.provide_context(&ctx, ctx_start);
+                }
+                Err(e) => panic!("grapheme segmentation failed: {:?}", e),
+            }
+        }
+    }
+
+    fn line_count(&self) -> usize {
+        self.lines().line_count()
+    }
+
+    fn line_start(&self, line: usize) -> Option<usize> {
+        self.lines().line_start(line)
+    }
+
+    fn line_end(&self, line: usize) -> usize {
+        self.lines().line_end(line)
+    }
+
+    // (byte column, line) of the byte offset `i`
+    fn point(&self, i: usize) -> (usize, usize) {
+        self.lines().point(i)
+    }
+
+    #[cfg(test)]
+    fn to_string(&self) -> String {
+        String::from_utf8(self.slice(0..self.len())).expect("BUG!!!")
+    }
+}
+
+// length of the utf8 char starting with the lead byte `b`
+pub fn utf8_char_len(b: u8) -> usize {
+    match b {
+        b if b < 0x80 => 1,
+        b if b >> 5 == 0b110 => 2,
+        b if b >> 4 == 0b1110 => 3,
+        b if b >> 3 == 0b11110 => 4,
+        _ => panic!("corrupted utf8"),
+    }
+}
+
+// pick a backend based on the size of the text
+pub fn init(s: String) -> Box<dyn TextStorage> {
+    if s.len() >= PIECE_TABLE_THRESHOLD {
+        Box::new(PieceTable::init(s))
+    } else {
+        Box::new(Buffer::init(s))
+    }
+}
+
+// the same behaviour is expected from every backend
+#[cfg(test)]
+macro_rules! storage_tests {
+    ($name:ident, $init:expr) => {
+        mod $name {
+            use super::*;
+            use crate::mark::Gravity;
+
+            fn init(s: &str) -> Box<dyn TextStorage> {
+                Box::new($init(String::from(s)))
+            }
+
+            #[test]
+            fn test_storage_init() {
+                let buf = init("a bc");
+                assert_eq!(buf.to_string(), "a bc");
+                assert_eq!(buf.cursor(), 0);
+                assert_eq!(buf.len(), 4);
+
+                let buf = init("");
+                assert_eq!(buf.to_string(), "");
+                assert_eq!(buf.len(), 0);
+            }
+
+            #[test]
+            fn test_storage_insert() {
+                let mut buf = init("a");
+                buf.insert('b');
+                assert_eq!(buf.to_string(), "ba");
+                assert_eq!(buf.cursor(), 1);
+
+                buf.insert('🧑');
+                assert_eq!(buf.to_string(), "b🧑a");
+                assert_eq!(buf.cursor(), 5);
+
+                for _ in 0..2000 {
+                    buf.insert('x');
+                }
+                assert_eq!(buf.len(), 2006);
+                assert_eq!(buf.byte_at(buf.cursor()), Some(b'a'));
+            }
+
+            #[test]
+            fn test_storage_move_ptr() {
+                let mut buf = init("h🌾e");
+                buf.move_ptr_backward();
+                assert_eq!(buf.cursor(), 0);
+
+                buf.move_ptr_forward();
+                buf.move_ptr_forward();
+                assert_eq!(buf.cursor(), 5);
+
+                buf.move_ptr_forward();
+                buf.move_ptr_forward();
+                assert_eq!(buf.cursor(), 6);
+
+                buf.move_ptr_backward();
+                buf.move_ptr_backward();
+                assert_eq!(buf.cursor(), 1);
+                assert_eq!(buf.to_string(), "h🌾e");
+            }
+
+            #[test]
+            fn test_storage_revert() {
+                let mut buf = init("hello");
+                buf.jump(5);
+                let deleted = buf.delete_before_ptr().unwrap();
+                let prev = buf.cursor();
+                buf.jump(0);
+                buf.revert_delete_before_ptr(prev, &deleted);
+                assert_eq!(buf.to_string(), "hello");
+                assert_eq!(buf.cursor(), 5);
+
+                buf.jump(1);
+                let deleted = buf.delete_after_ptr().unwrap();
+                buf.jump(3);
+                buf.revert_delete_after_ptr(1, &deleted);
+                assert_eq!(buf.to_string(), "hello");
+                assert_eq!(buf.cursor(), 1);
+
+                buf.jump(5);
+                buf.insert('🌾');
+                let deleted = buf.delete_before_ptr().unwrap();
+                assert_eq!(deleted, "🌾".as_bytes());
+                buf.revert_delete_before_ptr(5, &deleted);
+                assert_eq!(buf.to_string(), "hello🌾");
+
+                buf.jump(1);
+                buf.insert('x');
+                buf.insert('y');
+                buf.revert_insert(1, 2);
+                assert_eq!(buf.to_string(), "hello🌾");
+                assert_eq!(buf.cursor(), 1);
+            }
+
+            #[test]
+            fn test_storage_insert_str() {
+                let mut buf = init("ad");
+                buf.jump(1);
+                buf.insert_str("bc🌾\n");
+                assert_eq!(buf.to_string(), "abc🌾\nd");
+                assert_eq!(buf.cursor(), 8);
+                assert_eq!(buf.line_count(), 2);
+
+                buf.insert_str("");
+                assert_eq!(buf.cursor(), 8);
+
+                let s = "x".repeat(5000);
+                buf.jump(0);
+                buf.insert_str(&s);
+                assert_eq!(buf.len(), 5009);
+                assert_eq!(buf.cursor(), 5000);
+                assert_eq!(buf.byte_at(5000), Some(b'a'));
+            }
+
+            #[test]
+            fn test_storage_delete_range() {
+                let mut buf = init("hello\nbig\nworld");
+                assert_eq!(buf.delete_range(3..11), b"lo\nbig\nw");
+                assert_eq!(buf.to_string(), "helorld");
+                assert_eq!(buf.cursor(), 3);
+                assert_eq!(buf.line_count(), 1);
+
+                buf.jump(7);
+                assert_eq!(buf.delete_range(0..2), b"he");
+                assert_eq!(buf.cursor(), 0);
+                assert_eq!(buf.delete_range(1..1), b"");
+                assert_eq!(buf.to_string(), "lorld");
+            }
+
+            #[test]
+            fn test_storage_grapheme_boundaries() {
+                // farmer (ZWJ sequence), e + combining acute, two flags, hangul jamo, CRLF
+                let s = "a🧑\u{200D}🌾e\u{301}🇻🇳🇫🇷\u{1100}\u{1161}\u{11A8}\r\nb";
+                let buf = init(s);
+                let boundaries = [0, 1, 12, 15, 23, 31, 40, 42, 43];
+
+                for w in boundaries.windows(2) {
+                    assert_eq!(buf.next_grapheme_boundary(w[0]), w[1]);
+                    assert_eq!(buf.prev_grapheme_boundary(w[1]), w[0]);
+                }
+                assert_eq!(buf.next_grapheme_boundary(43), 43);
+                assert_eq!(buf.prev_grapheme_boundary(0), 0);
+            }
+
+            #[test]
+            fn test_storage_grapheme_boundaries_long_cluster() {
+                // a base char followed by more combining marks than fit in one chunk
+                let s = format!("x{}y", "\u{301}".repeat(100));
+                let buf = init(&s);
+
+                assert_eq!(buf.next_grapheme_boundary(0), 201);
+                assert_eq!(buf.prev_grapheme_boundary(201), 0);
+                assert_eq!(buf.prev_grapheme_boundary(202), 201);
+            }
+
+            #[test]
+            fn test_storage_delete_grapheme() {
+                let mut buf = init("a🧑\u{200D}🌾b");
+                buf.jump(12);
+                assert_eq!(buf.delete_grapheme_before_ptr().map(|b| b.len()), Some(11));
+                assert_eq!(buf.to_string(), "ab");
+                assert_eq!(buf.cursor(), 1);
+
+                buf.jump(0);
+                buf.insert_str("e\u{301}");
+                buf.jump(0);
+                assert_eq!(
+                    buf.delete_grapheme_after_ptr(),
+                    Some("e\u{301}".as_bytes().to_vec())
+                );
+                assert_eq!(buf.to_string(), "ab");
+
+                buf.jump(2);
+                assert_eq!(buf.delete_grapheme_after_ptr(), None);
+                buf.jump(0);
+                assert_eq!(buf.delete_grapheme_before_ptr(), None);
+            }
+
+            #[test]
+            fn test_storage_marks() {
+                let mut buf = init("hello world");
+                let left = buf.marks_mut().create(6, Gravity::Left);
+                let right = buf.marks_mut().create(6, Gravity::Right);
+                let end = buf.marks_mut().create(11, Gravity::Left);
+
+                buf.jump(6);
+                buf.insert_str("big ");
+                assert_eq!(buf.marks().get(left), Some(6));
+                assert_eq!(buf.marks().get(right), Some(10));
+                assert_eq!(buf.marks().get(end), Some(15));
+
+                buf.delete_range(0..8);
+                assert_eq!(buf.marks().get(left), Some(0));
+                assert_eq!(buf.marks().get(right), Some(2));
+                assert_eq!(buf.marks().get(end), Some(7));
+
+                buf.revert_delete_before_ptr(0, b"hello bi");
+                assert_eq!(buf.marks().get(right), Some(10));
+            }
+
+            #[test]
+            fn test_storage_iter_and_slice() {
+                let mut buf = init("ab\ncd");
+                buf.jump(3);
+                buf.insert('x');
+
+                assert_eq!(buf.iter_from(0).collect::<Vec<u8>>(), b"ab\nxcd");
+                assert_eq!(buf.iter_from(3).collect::<Vec<u8>>(), b"xcd");
+                assert_eq!(buf.slice(1..5), b"b\nxc");
+                assert_eq!(buf.iter_from(6).next(), None);
+            }
+
+            #[test]
+            fn test_storage_snapshot() {
+                let mut buf = init("ab\ncd");
+                let before = buf.snapshot();
+                buf.jump(3);
+                buf.insert('x');
+                buf.delete_range(0..1);
+                let after = buf.snapshot();
+
+                assert_eq!(before.text(), "ab\ncd");
+                assert_eq!(after.text(), "b\nxcd");
+                assert_eq!(after.version(), buf.version());
+                assert_eq!(after.version(), before.version() + 2);
+
+                // nothing changed in between, nothing is copied
+                let again = buf.snapshot();
+                assert!(
+                    after
+                        .chunks()
+                        .zip(again.chunks())
+                        .all(|(a, b)| a.as_ptr() == b.as_ptr())
+                );
+            }
+
+            #[test]
+            fn test_storage_lines() {
+                let mut buf = init("ab\ncd");
+                buf.jump(4);
+                buf.insert('\n');
+                assert_eq!(buf.line_count(), 3);
+                assert_eq!(buf.point(buf.cursor()), (0, 2));
+
+                buf.jump(0);
+                buf.delete_after_ptr();
+                buf.delete_after_ptr();
+                buf.delete_after_ptr();
+                assert_eq!(buf.to_string(), "c\nd");
+                assert_eq!(buf.line_start(1), Some(2));
+                assert_eq!(buf.line_end(0), 1);
+            }
+
+            #[test]
+            fn test_storage_delete_before_ptr_1() {
+                let mut buf = init("");
+                buf.insert('🧑'); // 4-byte char
+
+                buf.delete_before_ptr();
+                assert_eq!(buf.cursor(), 0);
+                assert_eq!(buf.to_string(), "");
+            }
+
+            #[test]
+            fn test_storage_delete_before_ptr_2() {
+                let mut buf = init("");
+                buf.insert('🧑'); // 4-byte char
+                buf.insert('a');
+
+                buf.delete_before_ptr();
+                assert_eq!(buf.cursor(), 4);
+                assert_eq!(buf.to_string(), "🧑");
+            }
+
+            #[test]
+            fn test_storage_delete_before_ptr_3() {
+                let mut buf = init("");
+                buf.insert('a');
+                buf.insert('🧑'); // 4-byte char
+
+                assert_eq!(buf.delete_before_ptr().map(|b| b.len()), Some(4));
+                assert_eq!(buf.cursor(), 1);
+                assert_eq!(buf.to_string(), "a");
+
+                buf.delete_before_ptr();
+                assert_eq!(buf.delete_before_ptr(), None);
+                assert_eq!(buf.to_string(), "");
+            }
+
+            #[test]
+            fn test_storage_delete_before_ptr_4() {
+                let mut buf = init("a");
+
+                buf.delete_before_ptr();
+                assert_eq!(buf.cursor(), 0);
+                assert_eq!(buf.to_string(), "a");
+            }
+
+            #[test]
+            fn test_storage_revert_delete_1() {
+                let mut buf = init("");
+                buf.insert('a');
+                buf.insert('b');
+                buf.insert('c');
+                let prev_iptr = buf.cursor();
+                let n = buf.delete_before_ptr().unwrap();
+
+                buf.move_ptr_backward();
+                buf.move_ptr_backward();
+                assert_eq!(buf.to_string(), "ab");
+
+                buf.revert_delete_before_ptr(prev_iptr, &n);
+                assert_eq!(buf.to_string(), "abc");
+            }
+
+            #[test]
+            fn test_storage_revert_delete_4() {
+                let mut buf = init("hello");
+                for _ in 0..4 {
+                    buf.move_ptr_forward();
+                }
+
+                let n = buf.delete_after_ptr().unwrap();
+                let prev_iptr = buf.cursor();
+                assert_eq!(buf.to_string(), "hell");
+
+                for _ in 0..5 {
+                    buf.move_ptr_backward();
+                }
+
+                buf.revert_delete_after_ptr(prev_iptr, &n);
+                assert_eq!(buf.to_string(), "hello");
+            }
+
+            #[test]
+            fn test_storage_revert_delete_2() {
+                let mut buf = init("hello");
+
+                for _ in 0..5 {
+                    buf.move_ptr_forward();
+                }
+
+                let n = buf.delete_before_ptr().unwrap();
+                let prev = buf.cursor();
+
+                for _ in 0..5 {
+                    buf.move_ptr_backward();
+                }
+
+                buf.revert_delete_before_ptr(prev, &n);
+                assert_eq!(buf.to_string(), "hello");
+            }
+
+            #[test]
+            fn test_storage_revert_delete_3() {
+                let mut buf = init("ab");
+                buf.move_ptr_forward();
+
+                let n = buf.delete_before_ptr().unwrap();
+                let prev_iptr = buf.cursor();
+
+                buf.move_ptr_forward();
+                assert_eq!(buf.to_string(), "b");
+
+                buf.revert_delete_before_ptr(prev_iptr, &n);
+                assert_eq!(buf.to_string(), "ab");
+            }
+
+            #[test]
+            fn test_storage_delete_before_ptr_5() {
+                let mut buf = init("");
+                buf.insert('a');
+                buf.insert('\u{1F9D1}');
+                buf.insert('\u{200D}');
+                buf.insert('\u{1F33E}');
+                buf.insert('b');
+
+                buf.delete_before_ptr();
+                assert_eq!(buf.to_string(), "a🧑‍🌾");
+
+                buf.delete_before_ptr();
+                assert_eq!(buf.to_string(), "a🧑‍");
+
+                buf.delete_before_ptr();
+                assert_eq!(buf.to_string(), "a🧑");
+
+                buf.delete_before_ptr();
+                assert_eq!(buf.to_string(), "a");
+
+                buf.delete_before_ptr();
+                assert_eq!(buf.to_string(), "");
+
+                assert_eq!(buf.cursor(), 0);
+            }
+
+            #[test]
+            fn test_storage_delete_after_ptr_1() {
+                let mut buf = init("🧑");
+                assert_eq!(buf.cursor(), 0);
+
+                buf.delete_after_ptr();
+                assert_eq!(buf.cursor(), 0);
+                assert_eq!(buf.to_string(), "");
+
+                buf.insert('a');
+                buf.delete_after_ptr();
+            }
+
+            #[test]
+            fn test_storage_delete_after_ptr_2() {
+                let mut buf = init("a");
+                assert_eq!(buf.cursor(), 0);
+
+                buf.delete_after_ptr();
+                assert_eq!(buf.cursor(), 0);
+                assert_eq!(buf.to_string(), "");
+            }
+
+            #[test]
+            fn test_storage_delete_after_ptr_3() {
+                let mut buf = init("");
+                assert_eq!(buf.cursor(), 0);
+
+                buf.delete_after_ptr();
+                assert_eq!(buf.cursor(), 0);
+                assert_eq!(buf.to_string(), "");
+            }
+
+            #[test]
+            fn test_storage_delete_after_ptr_4() {
+                let mut buf = init("hel🧑‍🌾");
+                assert_eq!(buf.cursor(), 0);
+
+                buf.delete_after_ptr();
+                assert_eq!(buf.to_string(), "el🧑‍🌾");
+
+                buf.delete_after_ptr();
+                assert_eq!(buf.to_string(), "l🧑‍🌾");
+
+                buf.delete_after_ptr();
+                assert_eq!(buf.to_string(), "🧑‍🌾");
+
+                buf.delete_after_ptr();
+                // \u{200D} is a zero-width joiner codepoint
+                assert_eq!(buf.to_string(), "\u{200D}🌾");
+
+                buf.delete_after_ptr();
+                assert_eq!(buf.to_string(), "🌾");
+
+                buf.delete_after_ptr();
+                assert_eq!(buf.to_string(), "");
+                assert_eq!(buf.delete_after_ptr(), None);
+            }
+
+            #[test]
+            fn test_storage_move_ptr_forward_1() {
+                let mut buf = init("");
+                buf.move_ptr_forward();
+                assert_eq!(buf.cursor(), 0);
+            }
+
+            #[test]
+            fn test_storage_move_ptr_forward_2() {
+                let mut buf = init("a");
+                buf.move_ptr_forward();
+                assert_eq!(buf.cursor(), 1);
+                assert_eq!(buf.to_string(), "a");
+            }
+
+            #[test]
+            fn test_storage_move_ptr_forward_3() {
+                let mut buf = init("a");
+                buf.insert('b');
+                buf.move_ptr_forward();
+                assert_eq!(buf.cursor(), 2);
+                assert_eq!(buf.to_string(), "ba");
+                buf.insert('c');
+                assert_eq!(buf.to_string(), "bac");
+            }
+
+            #[test]
+            fn test_storage_move_ptr_backward_1() {
+                let mut buf = init("");
+                buf.move_ptr_backward();
+                assert_eq!(buf.cursor(), 0);
+            }
+
+            #[test]
+            fn test_storage_move_ptr_backward_2() {
+                let mut buf = init("a");
+                buf.move_ptr_backward();
+                assert_eq!(buf.cursor(), 0);
+            }
+
+            #[test]
+            fn test_storage_move_ptr_backward_3() {
+                let mut buf = init("a");
+                buf.move_ptr_forward();
+                buf.move_ptr_backward();
+                assert_eq!(buf.cursor(), 0);
+            }
+
+            #[test]
+            fn test_storage_iter_1() {
+                let mut buf = init("");
+                buf.insert('a');
+                buf.move_ptr_backward();
+                buf.move_ptr_forward();
+
+                for b in buf.iter_from(0) {
+                    assert_eq!(b, b'a');
+                }
+            }
+
+            #[test]
+            fn test_storage_iter_2() {
+                let mut buf = init("h🌾el");
+                buf.move_ptr_forward();
+                buf.move_ptr_forward();
+
+                assert_eq!(buf.iter_from(0).collect::<Vec<u8>>(), "h🌾el".as_bytes());
+                assert_eq!(buf.iter_from(5).collect::<Vec<u8>>(), b"el");
+            }
+
+            #[test]
+            fn test_storage_lines_1() {
+                let mut buf = init("ab\ncd");
+                buf.jump(4);
+                buf.insert('\n');
+                assert_eq!(buf.line_count(), 3);
+                assert_eq!(buf.point(buf.cursor()), (0, 2));
+
+                let deleted = buf.delete_before_ptr().unwrap();
+                assert_eq!(buf.line_count(), 2);
+                assert_eq!(buf.point(buf.cursor()), (1, 1));
+
+                buf.revert_delete_before_ptr(4, &deleted);
+                assert_eq!(buf.line_count(), 3);
+
+                buf.jump(2);
+                let deleted = buf.delete_after_ptr().unwrap();
+                assert_eq!(buf.to_string(), "abc\nd");
+                assert_eq!(buf.line_start(1), Some(4));
+
+                buf.revert_delete_after_ptr(2, &deleted);
+                assert_eq!(buf.line_start(1), Some(3));
+                assert_eq!(buf.line_end(1), 4);
+
+                buf.revert_insert(4, 1);
+                assert_eq!(buf.to_string(), "ab\ncd");
+                assert_eq!(buf.line_count(), 2);
+            }
+
+            #[test]
+            fn test_storage_floor_char_boundary() {
+                let buf = init("a🌾b");
+                assert_eq!(buf.floor_char_boundary(0), 0);
+                assert_eq!(buf.floor_char_boundary(3), 1);
+                assert_eq!(buf.floor_char_boundary(5), 5);
+                assert_eq!(buf.floor_char_boundary(42), 6);
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    storage_tests!(gap_buffer, Buffer::init);
+    storage_tests!(piece_table, PieceTable::init);
+}
@@ -1,9 +1,60 @@
-use crate::buffer::Buffer;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
 
+use crate::storage::{TextStorage, utf8_char_len};
+
+// Every state the text has been in is a node of a tree, and each node holds the
+// change that leads to it from its parent:
+//
+//         root
+//          |
+//          1        "hello"
+//         / \
+//        2   3      " world" was undone, then " there" typed: both are kept
+//
+// Undo walks up towards the root, redo walks down the branch visited last, and
+// editing after an undo starts a new branch instead of dropping the undone changes.
+//
+// The text of every change is kept in one shared arena, and the oldest changes are
+// forgotten once the tree grows past its limits, see `limit`.
 #[derive(Debug)]
 pub struct UndoManager {
-    undos: Vec<Command>,
-    redos: Vec<Command>,
+    nodes: Vec<Node>,
+    current: usize,
+    // the state that was saved last, the root is the text as it was loaded
+    // none once it was forgotten
+    saved: Option<usize>,
+    // commands pushed since the outermost `begin_group`
+    group: Vec<Command>,
+    // insertion point before the first and after the last of them
+    group_cursor: Option<(usize, usize)>,
+    // nesting level of `begin_group`
+    depth: usize,
+    arena: Arena,
+    // at most this many nodes besides the root, and bytes in the arena
+    max_steps: usize,
+    max_bytes: usize,
+    coalesce: Coalesce,
+}
+
+// what stops consecutive typing or deleting from being merged into one undo step,
+// besides the changes not being next to each other
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coalesce {
+    // a pause longer than this, none to never break on time
+    pub idle: Option<Duration>,
+    pub boundary: Boundary,
+    // the cursor moved without an edit in between
+    pub on_move: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Boundary {
+    Off,
+    // right after a line break
+    Line,
+    // where a word starts after whitespace, line breaks included
+    Word,
 }
 
 #[derive(Debug)]
@@ -11,112 +62,1314 @@ pub enum Command {
     Insert((usize, String)),        // (insertion pointer, num of bytes inserted)
     DeleteBefore((usize, Vec<u8>)), // (insertion pointer, deleted bytes)
     DeleteAfter((usize, Vec<u8>)),  // (insertion pointer, deleted bytes)
-    Checkpoint,                     // special checkpoint (i.e when saved)
+    Group(Vec<Command>),            // undone and redone as one step, in order
+}
+
+// a `Command` once in the tree, its bytes moved to the arena
+#[derive(Debug)]
+enum Change {
+    Insert(usize, Span),
+    DeleteBefore(usize, Span),
+    DeleteAfter(usize, Span),
+    Group(Vec<Change>),
+}
+
+// `len` bytes of the arena from `start`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Span {
+    start: usize,
+    len: usize,
+}
+
+// Every byte inserted or deleted, back to back. Forgotten changes leave holes, and so
+// do changes that grow while they are not at the end, as they are copied there first.
+// The holes are squeezed out once they outgrow what is still in use.
+//
+// The text of a `DeleteBefore` is kept back to front: backspacing deletes from the
+// end of the run towards its start, and reversed its bytes only ever grow at the end.
+#[derive(Debug, Default)]
+struct Arena {
+    bytes: Vec<u8>,
+    // bytes some change still refers to
+    live: usize,
+}
+
+#[derive(Debug)]
+struct Node {
+    // the change from the parent, none for the root
+    cmd: Option<Change>,
+    // insertion point before and after the change, restored by undo and redo
+    cursor: (usize, usize),
+    parent: usize,
+    children: Vec<usize>,
+    // index in `children` of the branch redo follows
+    active: usize,
+    // when the change was made
+    time: SystemTime,
+    // nothing is merged into a sealed node anymore, i.e. it was saved or the
+    // cursor moved away
+    sealed: bool,
+}
+
+// one line of the undo tree browser
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeRow {
+    pub id: usize,
+    // number of branches taken away from the oldest child on the way from the root
+    pub depth: usize,
+    pub time: SystemTime,
+    pub current: bool,
 }
 
-// TODO: fix this frequently small string allocation
 impl UndoManager {
     pub fn new() -> Self {
+        let root = Node {
+            cmd: None,
+            cursor: (0, 0),
+            parent: 0,
+            children: Vec::new(),
+            active: 0,
+            time: SystemTime::UNIX_EPOCH,
+            sealed: true,
+        };
+
         Self {
-            undos: Vec::with_capacity(8),
-            redos: Vec::with_capacity(8),
+            nodes: vec![root],
+            current: 0,
+            saved: Some(0),
+            group: Vec::new(),
+            group_cursor: None,
+            depth: 0,
+            arena: Arena::default(),
+            max_steps: usize::MAX,
+            max_bytes: usize::MAX,
+            coalesce: Coalesce::ADJACENT,
         }
     }
 
-    pub fn push(&mut self, cmd: Command) {
-        let mut accumulate = false;
+    pub fn coalesce(&mut self, policy: Coalesce) {
+        self.coalesce = policy;
+    }
 
-        let final_cmd = if let Some(last_cmd) = self.undos.last() {
-            match last_cmd {
-                Command::DeleteBefore((last_i, last_val)) => match cmd {
-                    Command::DeleteBefore((i, mut val)) if i + val.len() == *last_i => {
-                        accumulate = true;
+    // the cursor moved on its own, not by a change
+    pub fn moved(&mut self) {
+        if self.coalesce.on_move {
+            self.boundary();
+        }
+    }
 
-                        val.extend(last_val);
-                        Command::DeleteBefore((i, val))
-                    }
-                    _ => cmd,
-                },
-                Command::DeleteAfter((last_i, last_val)) => match cmd {
-                    Command::DeleteAfter((i, val)) if i == *last_i => {
-                        accumulate = true;
-
-                        let mut deleted = last_val.clone();
-                        deleted.extend(val);
-                        Command::DeleteAfter((i, deleted))
-                    }
-                    _ => cmd,
-                },
-                Command::Insert((last_i, last_s)) => match cmd {
-                    Command::Insert((i, s)) if last_i + last_s.len() == i => {
-                        accumulate = true;
-
-                        let mut val = last_s.clone();
-                        val.push_str(&s);
-                        Command::Insert((*last_i, val))
-                    }
-                    _ => cmd,
-                },
-                Command::Checkpoint => {
-                    accumulate = true;
+    // the next change starts a new undo step
+    pub fn boundary(&mut self) {
+        self.nodes[self.current].sealed = true;
+    }
+
+    // keeps at most `steps` changes and `bytes` of inserted and deleted text,
+    // the oldest changes are forgotten first
+    pub fn limit(&mut self, steps: usize, bytes: usize) {
+        self.max_steps = steps;
+        self.max_bytes = bytes;
+        self.forget();
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
 
-                    cmd
+    // `before` and `after` are the insertion point before and after the change
+    pub fn push(&mut self, cmd: Command, before: usize, after: usize) {
+        if self.depth > 0 {
+            self.group.push(cmd);
+            self.group_cursor = Some((self.group_cursor.map_or(before, |c| c.0), after));
+            return;
+        }
+
+        let now = SystemTime::now();
+        let id = self.nodes.len();
+        let node = &mut self.nodes[self.current];
+        let idle = self.coalesce.idle.is_some_and(|idle| {
+            now.duration_since(node.time)
+                .is_ok_and(|pause| pause > idle)
+        });
+
+        // commands are only merged at the tip of a branch, merging into a node that
+        // has children would change the state its children start from
+        let cmd = match node.cmd.as_mut() {
+            Some(last) if node.children.is_empty() && !node.sealed && !idle => {
+                match merge(&mut self.arena, last, cmd, self.coalesce.boundary) {
+                    Ok(()) => {
+                        node.cursor.1 = after;
+                        node.time = now;
+                        self.forget();
+                        // the change may have been moved to the end of the arena to grow,
+                        // leaving a hole even when nothing is forgotten
+                        self.squeeze();
+                        return;
+                    }
+                    Err(cmd) => cmd,
                 }
             }
+            _ => cmd,
+        };
+
+        node.active = node.children.len();
+        node.children.push(id);
+        let cmd = self.arena.store(cmd);
+        self.nodes.push(Node {
+            cmd: Some(cmd),
+            cursor: (before, after),
+            parent: self.current,
+            children: Vec::new(),
+            active: 0,
+            time: now,
+            sealed: false,
+        });
+        self.current = id;
+        self.forget();
+    }
+
+    // everything pushed until the matching `end_group` becomes a single undo step,
+    // groups can be nested
+    pub fn begin_group(&mut self) {
+        self.depth += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth > 0 {
+            return;
+        }
+
+        let mut cmds = std::mem::take(&mut self.group);
+        let Some((before, after)) = self.group_cursor.take() else {
+            return;
+        };
+
+        match cmds.len() {
+            // a lone command can still merge with its neighbours, e.g. typing
+            1 => self.push(cmds.pop().unwrap(), before, after),
+            _ => self.push(Command::Group(cmds), before, after),
+        }
+    }
+
+    // marks the current state as saved, the next command starts a new undo step
+    pub fn checkpoint(&mut self) {
+        self.nodes[self.current].sealed = true;
+        self.saved = Some(self.current);
+    }
+
+    // whether the text is the one that was saved last, whatever undo and redo happened since
+    pub fn is_at_checkpoint(&self) -> bool {
+        self.saved == Some(self.current)
+    }
+
+    pub fn undo(&mut self, buf: &mut dyn TextStorage) {
+        let node = &self.nodes[self.current];
+        let Some(cmd) = node.cmd.as_ref() else {
+            return;
+        };
+
+        revert(cmd, &self.arena, buf);
+        buf.jump(node.cursor.0);
+
+        let parent = node.parent;
+        self.nodes[parent].active = self.child_index(self.current);
+        self.current = parent;
+    }
+
+    pub fn redo(&mut self, buf: &mut dyn TextStorage) {
+        let node = &self.nodes[self.current];
+        let Some(&child) = node.children.get(node.active) else {
+            return;
+        };
+
+        if let Some(cmd) = self.nodes[child].cmd.as_ref() {
+            apply(cmd, &self.arena, buf);
+        }
+        buf.jump(self.nodes[child].cursor.1);
+
+        self.current = child;
+    }
+
+    // moves to the next (or previous) sibling of the current state, false when there is none
+    pub fn switch_branch(&mut self, buf: &mut dyn TextStorage, forward: bool) -> bool {
+        let parent = self.nodes[self.current].parent;
+        let n = self.nodes[parent].children.len();
+        if self.current == 0 || n < 2 {
+            return false;
+        }
+
+        let i = self.child_index(self.current);
+        let j = if forward {
+            (i + 1) % n
         } else {
-            cmd
+            (i + n - 1) % n
         };
 
-        if accumulate {
-            self.undos.pop();
+        self.undo(buf);
+        self.nodes[parent].active = j;
+        self.redo(buf);
+
+        true
+    }
+
+    // undoes up to the closest common ancestor, then redoes down to `target`
+    pub fn jump(&mut self, buf: &mut dyn TextStorage, target: usize) {
+        if target >= self.nodes.len() {
+            return;
         }
 
-        self.undos.push(final_cmd);
-        self.redos.clear();
+        let mut path = vec![target];
+        while let Some(&id) = path.last()
+            && id != 0
+        {
+            path.push(self.nodes[id].parent);
+        }
+
+        // where each node of the path is in it, a long history is not scanned per step
+        let on_path: HashMap<usize, usize> =
+            path.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        while !on_path.contains_key(&self.current) {
+            self.undo(buf);
+        }
+
+        let i = on_path[&self.current];
+        for &id in path[..i].iter().rev() {
+            self.nodes[self.current].active = self.child_index(id);
+            self.redo(buf);
+        }
     }
 
-    pub fn undo(&mut self, buf: &mut Buffer) {
-        if let Some(cmd) = self.undos.pop() {
-            match cmd {
-                Command::Insert((prev, ref inserted)) => buf.revert_insert(prev, inserted.len()),
-                Command::DeleteBefore((prev, ref deleted)) => {
-                    buf.revert_delete_before_ptr(prev, deleted)
+    // the state the text was in at `time`, i.e. the last change made before it
+    pub fn node_at(&self, time: SystemTime) -> usize {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.time <= time)
+            .max_by_key(|(_, node)| node.time)
+            .map_or(0, |(id, _)| id)
+    }
+
+    // the whole tree, depth first: a node, then the branches it starts, then its oldest child
+    pub fn rows(&self) -> Vec<TreeRow> {
+        let mut rows = Vec::with_capacity(self.nodes.len());
+        let mut stack = vec![(0, 0)];
+
+        while let Some((id, depth)) = stack.pop() {
+            let node = &self.nodes[id];
+            rows.push(TreeRow {
+                id,
+                depth,
+                time: node.time,
+                current: id == self.current,
+            });
+
+            if let Some((first, branches)) = node.children.split_first() {
+                stack.push((*first, depth));
+                for child in branches.iter().rev() {
+                    stack.push((*child, depth + 1));
                 }
-                Command::DeleteAfter((prev, ref deleted)) => {
-                    buf.revert_delete_after_ptr(prev, deleted)
+            }
+        }
+
+        rows
+    }
+
+    fn child_index(&self, id: usize) -> usize {
+        let parent = self.nodes[id].parent;
+        self.nodes[parent]
+            .children
+            .iter()
+            .position(|c| *c == id)
+            .unwrap_or(0)
+    }
+
+    // Drops changes until the tree is within its limits. Branches that do not lead
+    // to the current state go first, oldest first, then the root moves one step
+    // towards the current state, i.e. the text it had is out of reach from then on.
+    fn forget(&mut self) {
+        let mut steps = self.nodes.len() - 1;
+        let mut bytes = self.arena.live;
+        if steps <= self.max_steps && bytes <= self.max_bytes {
+            return;
+        }
+
+        // a bit more than needed goes at once, so the next pushes do not have to
+        // walk the whole tree again
+        let (max_steps, max_bytes) = (
+            self.max_steps - self.max_steps / 10,
+            self.max_bytes - self.max_bytes / 10,
+        );
+
+        let mut on_path = vec![false; self.nodes.len()];
+        let mut id = self.current;
+        on_path[id] = true;
+        while id != 0 {
+            id = self.nodes[id].parent;
+            on_path[id] = true;
+        }
+
+        let mut gone = vec![false; self.nodes.len()];
+        let mut root = 0;
+        while steps > max_steps || bytes > max_bytes {
+            let children = &self.nodes[root].children;
+            // children are in creation order
+            if let Some(&branch) = children.iter().find(|c| !on_path[**c] && !gone[**c]) {
+                let mut stack = vec![branch];
+                while let Some(id) = stack.pop() {
+                    gone[id] = true;
+                    steps -= 1;
+                    bytes -= self.nodes[id].cmd.as_ref().map_or(0, Change::len);
+                    stack.extend(&self.nodes[id].children);
                 }
-                Command::Checkpoint => {}
+            } else if let Some(&next) = children.iter().find(|c| on_path[**c]) {
+                gone[root] = true;
+                steps -= 1;
+                bytes -= self.nodes[next].cmd.as_ref().map_or(0, Change::len);
+                root = next;
+            } else {
+                break;
             }
+        }
+
+        // what is left keeps its order, so the new root comes first
+        let mut ids = vec![usize::MAX; self.nodes.len()];
+        let mut nodes = Vec::with_capacity(steps + 1);
+        for (id, node) in std::mem::take(&mut self.nodes).into_iter().enumerate() {
+            if !gone[id] {
+                ids[id] = nodes.len();
+                nodes.push(node);
+            }
+        }
 
-            self.redos.push(cmd);
+        for node in &mut nodes {
+            let active = node.children.get(node.active).copied();
+            node.children.retain(|c| !gone[*c]);
+            node.active = node
+                .children
+                .iter()
+                .position(|c| Some(*c) == active)
+                .unwrap_or(0);
+            node.children.iter_mut().for_each(|c| *c = ids[*c]);
+            node.parent = ids[node.parent];
         }
+
+        nodes[0].cmd = None;
+        nodes[0].parent = 0;
+        nodes[0].sealed = true;
+        self.nodes = nodes;
+        self.current = ids[self.current];
+        self.saved = self.saved.map(|id| ids[id]).filter(|id| *id != usize::MAX);
+        self.arena.live = bytes;
+        self.squeeze();
     }
 
-    pub fn redo(&mut self, buf: &mut Buffer) {
-        if let Some(cmd) = self.redos.pop() {
-            match cmd {
-                Command::Insert((prev, ref inserted)) => {
-                    buf.jump(prev);
-                    for c in inserted.chars() {
-                        buf.insert(c);
-                    }
-                }
-                Command::DeleteBefore((prev, ref deleted)) => {
-                    buf.jump(prev + deleted.len());
-                    for _ in 0..deleted.len() {
-                        buf.delete_before_ptr();
-                    }
-                }
-                Command::DeleteAfter((prev, ref deleted)) => {
-                    buf.jump(prev);
-                    for _ in 0..deleted.len() {
-                        buf.delete_after_ptr();
-                    }
+    // rewrites the arena without the holes once they take more room than the rest
+    fn squeeze(&mut self) {
+        if self.arena.bytes.len() <= 2 * self.arena.live {
+            return;
+        }
+
+        let mut bytes = Vec::with_capacity(self.arena.live);
+        for cmd in self.nodes.iter_mut().filter_map(|node| node.cmd.as_mut()) {
+            cmd.for_each_span(&mut |span| {
+                let start = bytes.len();
+                bytes.extend_from_slice(&self.arena.bytes[span.start..span.start + span.len]);
+                span.start = start;
+            });
+        }
+
+        self.arena.bytes = bytes;
+    }
+}
+
+impl Coalesce {
+    // only changes next to each other are merged
+    pub const ADJACENT: Self = Self {
+        idle: None,
+        boundary: Boundary::Off,
+        on_move: false,
+    };
+}
+
+impl Boundary {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(Self::Off),
+            "line" => Some(Self::Line),
+            "word" => Some(Self::Word),
+            _ => None,
+        }
+    }
+
+    // whether there is one where the text `left` meets the text `right`
+    fn between(&self, left: &[u8], right: &[u8]) -> bool {
+        let last = str::from_utf8(left)
+            .ok()
+            .and_then(|s| s.chars().next_back());
+        let first = str::from_utf8(right).ok().and_then(|s| s.chars().next());
+
+        match self {
+            Self::Off => false,
+            Self::Line => last == Some('\n'),
+            Self::Word => {
+                last.is_some_and(char::is_whitespace) && first.is_some_and(|c| !c.is_whitespace())
+            }
+        }
+    }
+}
+
+impl Change {
+    // bytes it takes in the arena
+    fn len(&self) -> usize {
+        match self {
+            Self::Insert(_, span) | Self::DeleteBefore(_, span) | Self::DeleteAfter(_, span) => {
+                span.len
+            }
+            Self::Group(changes) => changes.iter().map(Change::len).sum(),
+        }
+    }
+
+    // the length of a text of `len` bytes once the change is applied to it, none if
+    // the change does not fit in it
+    fn apply_len(&self, len: usize) -> Option<usize> {
+        match self {
+            Self::Insert(i, span) => (*i <= len).then_some(len + span.len),
+            Self::DeleteBefore(i, span) | Self::DeleteAfter(i, span) => {
+                (i.checked_add(span.len)? <= len).then_some(len - span.len)
+            }
+            Self::Group(changes) => changes.iter().try_fold(len, |len, c| c.apply_len(len)),
+        }
+    }
+
+    // the length of the text before the change, it being `len` bytes after it
+    fn revert_len(&self, len: usize) -> Option<usize> {
+        match self {
+            Self::Insert(_, span) => len.checked_sub(span.len),
+            Self::DeleteBefore(_, span) | Self::DeleteAfter(_, span) => len.checked_add(span.len),
+            Self::Group(changes) => changes
+                .iter()
+                .rev()
+                .try_fold(len, |len, c| c.revert_len(len)),
+        }
+    }
+
+    fn for_each_span(&mut self, f: &mut dyn FnMut(&mut Span)) {
+        match self {
+            Self::Insert(_, span) | Self::DeleteBefore(_, span) | Self::DeleteAfter(_, span) => {
+                f(span)
+            }
+            Self::Group(changes) => changes.iter_mut().for_each(|c| c.for_each_span(f)),
+        }
+    }
+}
+
+impl Arena {
+    fn get(&self, span: Span) -> &[u8] {
+        &self.bytes[span.start..span.start + span.len]
+    }
+
+    // the bytes of a span kept back to front, in order
+    fn get_rev(&self, span: Span) -> Vec<u8> {
+        self.get(span).iter().rev().copied().collect()
+    }
+
+    // the first char of a span kept back to front, in order
+    fn first_rev(&self, span: Span) -> Vec<u8> {
+        let bytes = self.get(span);
+        let n = bytes.last().map_or(0, |b| utf8_char_len(*b));
+
+        bytes[bytes.len() - n..].iter().rev().copied().collect()
+    }
+
+    fn add(&mut self, bytes: &[u8]) -> Span {
+        let span = Span {
+            start: self.bytes.len(),
+            len: bytes.len(),
+        };
+        self.bytes.extend_from_slice(bytes);
+        self.live += bytes.len();
+
+        span
+    }
+
+    fn store(&mut self, cmd: Command) -> Change {
+        match cmd {
+            Command::Insert((i, s)) => Change::Insert(i, self.add(s.as_bytes())),
+            Command::DeleteBefore((i, mut bytes)) => {
+                bytes.reverse();
+                Change::DeleteBefore(i, self.add(&bytes))
+            }
+            Command::DeleteAfter((i, bytes)) => Change::DeleteAfter(i, self.add(&bytes)),
+            Command::Group(cmds) => {
+                Change::Group(cmds.into_iter().map(|cmd| self.store(cmd)).collect())
+            }
+        }
+    }
+
+    // the bytes of `span` then `suffix`, in place when `span` is at the end, which
+    // it is when typing
+    fn grow(&mut self, span: Span, suffix: &[u8]) -> Span {
+        let mut start = span.start;
+        if start + span.len != self.bytes.len() {
+            start = self.bytes.len();
+            self.bytes
+                .extend_from_within(span.start..span.start + span.len);
+        }
+
+        self.bytes.extend_from_slice(suffix);
+        self.live += suffix.len();
+
+        Span {
+            start,
+            len: span.len + suffix.len(),
+        }
+    }
+}
+
+// on-disk layout of the tree, all numbers are little endian u64:
+//
+// MAGIC, number of nodes, saved node, then for every node but the root:
+//   parent, active, cursor before, cursor after, seconds, nanoseconds, sealed, command
+// a command is a tag (insert, delete before, delete after, group) followed by either
+// its offset and byte length + bytes, or the number of commands in the group and them
+const MAGIC: &[u8] = b"kame-undo 1\n";
+
+impl UndoManager {
+    // no change was made yet
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == 1
+    }
+
+    // none when the saved state was forgotten, there would be no way to tell which
+    // state the file on disk is
+    pub fn encode(&self) -> Option<Vec<u8>> {
+        let saved = self.saved?;
+        let mut out = MAGIC.to_vec();
+        put(&mut out, self.nodes.len() as u64);
+        put(&mut out, saved as u64);
+
+        for node in &self.nodes[1..] {
+            let time = node
+                .time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+
+            put(&mut out, node.parent as u64);
+            put(&mut out, node.active as u64);
+            put(&mut out, node.cursor.0 as u64);
+            put(&mut out, node.cursor.1 as u64);
+            put(&mut out, time.as_secs());
+            put(&mut out, time.subsec_nanos() as u64);
+            put(&mut out, node.sealed as u64);
+            if let Some(cmd) = &node.cmd {
+                encode_change(&mut out, cmd, &self.arena);
+            }
+        }
+
+        Some(out)
+    }
+
+    // the tree written by `encode`, sitting at its saved state, for a text of `len` bytes
+    // none if the bytes are not a well formed tree, or if it does not fit the text
+    pub fn decode(bytes: &[u8], len: usize) -> Option<Self> {
+        let mut r = Reader(bytes.strip_prefix(MAGIC)?);
+        let n = r.usize()?;
+        let saved = r.usize()?;
+        let mut undo = Self::new();
+
+        for id in 1..n {
+            let parent = r.usize()?;
+            if parent >= id {
+                return None;
+            }
+
+            let active = r.usize()?;
+            let cursor = (r.usize()?, r.usize()?);
+            let time = Duration::new(r.u64()?, r.u64()?.try_into().ok()?);
+            let sealed = r.u64()? != 0;
+            let cmd = decode_command(&mut r)?;
+            let cmd = undo.arena.store(cmd);
+
+            undo.nodes[parent].children.push(id);
+            undo.nodes.push(Node {
+                cmd: Some(cmd),
+                cursor,
+                parent,
+                children: Vec::new(),
+                active,
+                time: SystemTime::UNIX_EPOCH.checked_add(time)?,
+                sealed,
+            });
+        }
+
+        if !r.0.is_empty() || saved >= undo.nodes.len() {
+            return None;
+        }
+
+        undo.current = saved;
+        undo.saved = Some(saved);
+        undo.fits(len).then_some(undo)
+    }
+
+    // whether every change and cursor of a decoded tree stays within the text, `len`
+    // being its length at the saved state
+    fn fits(&self, len: usize) -> bool {
+        let mut root_len = Some(len);
+        let mut id = self.current;
+        while id != 0 {
+            let node = &self.nodes[id];
+            root_len = root_len.and_then(|len| node.cmd.as_ref()?.revert_len(len));
+            id = node.parent;
+        }
+
+        let mut lens = vec![0; self.nodes.len()];
+        let Some(root_len) = root_len else {
+            return false;
+        };
+        lens[0] = root_len;
+
+        // parents come before their children
+        for (id, node) in self.nodes.iter().enumerate() {
+            if id > 0 {
+                let before = lens[node.parent];
+                let Some(after) = node.cmd.as_ref().and_then(|cmd| cmd.apply_len(before)) else {
+                    return false;
+                };
+                if node.cursor.0 > before || node.cursor.1 > after {
+                    return false;
                 }
-                Command::Checkpoint => {}
+                lens[id] = after;
             }
 
-            self.undos.push(cmd);
+            if node.active > 0 && node.active >= node.children.len() {
+                return false;
+            }
         }
+
+        true
+    }
+}
+
+fn put(out: &mut Vec<u8>, n: u64) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn encode_change(out: &mut Vec<u8>, cmd: &Change, arena: &Arena) {
+    let (tag, i, span) = match cmd {
+        Change::Insert(i, span) => (0, i, span),
+        Change::DeleteBefore(i, span) => {
+            put(out, 1);
+            put(out, *i as u64);
+            put(out, span.len as u64);
+            out.extend_from_slice(&arena.get_rev(*span));
+            return;
+        }
+        Change::DeleteAfter(i, span) => (2, i, span),
+        Change::Group(cmds) => {
+            put(out, 3);
+            put(out, cmds.len() as u64);
+            cmds.iter().for_each(|cmd| encode_change(out, cmd, arena));
+            return;
+        }
+    };
+
+    put(out, tag);
+    put(out, *i as u64);
+    put(out, span.len as u64);
+    out.extend_from_slice(arena.get(*span));
+}
+
+fn decode_command(r: &mut Reader) -> Option<Command> {
+    let tag = r.u64()?;
+    if tag == 3 {
+        let n = r.usize()?;
+        let cmds = (0..n)
+            .map(|_| decode_command(r))
+            .collect::<Option<Vec<Command>>>()?;
+
+        return Some(Command::Group(cmds));
+    }
+
+    let i = r.usize()?;
+    let n = r.usize()?;
+    let bytes = r.take(n)?.to_vec();
+    match tag {
+        0 => Some(Command::Insert((i, String::from_utf8(bytes).ok()?))),
+        1 => Some(Command::DeleteBefore((i, bytes))),
+        2 => Some(Command::DeleteAfter((i, bytes))),
+        _ => None,
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if n > self.0.len() {
+            return None;
+        }
+
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+
+        Some(head)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        self.u64()?.try_into().ok()
+    }
+}
+
+fn revert(cmd: &Change, arena: &Arena, buf: &mut dyn TextStorage) {
+    match cmd {
+        Change::Insert(prev, span) => buf.revert_insert(*prev, span.len),
+        Change::DeleteBefore(prev, span) => {
+            buf.revert_delete_before_ptr(*prev, &arena.get_rev(*span))
+        }
+        Change::DeleteAfter(prev, span) => buf.revert_delete_after_ptr(*prev, arena.get(*span)),
+        Change::Group(cmds) => cmds.iter().rev().for_each(|cmd| revert(cmd, arena, buf)),
+    }
+}
+
+fn apply(cmd: &Change, arena: &Arena, buf: &mut dyn TextStorage) {
+    match cmd {
+        Change::Insert(prev, span) => {
+            buf.jump(*prev);
+            buf.insert_str(str::from_utf8(arena.get(*span)).expect("corrupted utf8"));
+        }
+        Change::DeleteBefore(prev, span) | Change::DeleteAfter(prev, span) => {
+            buf.delete_range(*prev..*prev + span.len);
+        }
+        Change::Group(cmds) => cmds.iter().for_each(|cmd| apply(cmd, arena, buf)),
+    }
+}
+
+// consecutive typing or deleting becomes a single change, unless there is a
+// `boundary` between the two
+fn merge(
+    arena: &mut Arena,
+    last: &mut Change,
+    cmd: Command,
+    boundary: Boundary,
+) -> Result<(), Command> {
+    match (last, cmd) {
+        (Change::DeleteBefore(last_i, span), Command::DeleteBefore((i, mut val)))
+            if i + val.len() == *last_i && !boundary.between(&val, &arena.first_rev(*span)) =>
+        {
+            val.reverse();
+            *span = arena.grow(*span, &val);
+            *last_i = i;
+            Ok(())
+        }
+        (Change::DeleteAfter(last_i, span), Command::DeleteAfter((i, val)))
+            if i == *last_i && !boundary.between(arena.get(*span), &val) =>
+        {
+            *span = arena.grow(*span, &val);
+            Ok(())
+        }
+        (Change::Insert(last_i, span), Command::Insert((i, s)))
+            if *last_i + span.len == i && !boundary.between(arena.get(*span), s.as_bytes()) =>
+        {
+            *span = arena.grow(*span, s.as_bytes());
+            Ok(())
+        }
+        (_, cmd) => Err(cmd),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::buffer::Buffer;
+
+    fn insert(undo: &mut UndoManager, buf: &mut Buffer, s: &str) {
+        let prev = buf.cursor();
+        buf.insert_str(s);
+        undo.push(Command::Insert((prev, s.to_string())), prev, buf.cursor());
+    }
+
+    #[test]
+    fn test_undo_branch_1() {
+        let mut buf = Buffer::init(String::new());
+        let mut undo = UndoManager::new();
+        insert(&mut undo, &mut buf, "hello");
+        undo.checkpoint();
+        insert(&mut undo, &mut buf, " world");
+        undo.undo(&mut buf);
+        insert(&mut undo, &mut buf, " there");
+        assert_eq!(buf.to_string(), "hello there");
+
+        assert!(undo.switch_branch(&mut buf, true));
+        assert_eq!(buf.to_string(), "hello world");
+        assert!(undo.switch_branch(&mut buf, false));
+        assert_eq!(buf.to_string(), "hello there");
+
+        undo.undo(&mut buf);
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "");
+        undo.redo(&mut buf);
+        undo.redo(&mut buf);
+        assert_eq!(buf.to_string(), "hello there");
+    }
+
+    #[test]
+    fn test_undo_jump_1() {
+        let mut buf = Buffer::init(String::new());
+        let mut undo = UndoManager::new();
+        insert(&mut undo, &mut buf, "a");
+        undo.checkpoint();
+        insert(&mut undo, &mut buf, "b");
+        let ab = undo.current();
+        undo.undo(&mut buf);
+        insert(&mut undo, &mut buf, "c");
+        undo.checkpoint();
+        insert(&mut undo, &mut buf, "d");
+        assert_eq!(buf.to_string(), "acd");
+
+        undo.jump(&mut buf, ab);
+        assert_eq!(buf.to_string(), "ab");
+        assert_eq!(undo.current(), ab);
+
+        undo.jump(&mut buf, 0);
+        assert_eq!(buf.to_string(), "");
+        undo.redo(&mut buf);
+        undo.redo(&mut buf);
+        assert_eq!(buf.to_string(), "ab");
+    }
+
+    #[test]
+    fn test_undo_node_at_1() {
+        let mut buf = Buffer::init(String::new());
+        let mut undo = UndoManager::new();
+        insert(&mut undo, &mut buf, "a");
+        undo.checkpoint();
+        insert(&mut undo, &mut buf, "b");
+
+        let now = SystemTime::now();
+        undo.nodes[1].time = now - Duration::from_secs(600);
+        undo.nodes[2].time = now - Duration::from_secs(60);
+
+        assert_eq!(undo.node_at(now), 2);
+        assert_eq!(undo.node_at(now - Duration::from_secs(300)), 1);
+        assert_eq!(undo.node_at(now - Duration::from_secs(3600)), 0);
+    }
+
+    #[test]
+    fn test_undo_rows_1() {
+        let mut buf = Buffer::init(String::new());
+        let mut undo = UndoManager::new();
+        insert(&mut undo, &mut buf, "a");
+        undo.checkpoint();
+        insert(&mut undo, &mut buf, "b");
+        undo.undo(&mut buf);
+        insert(&mut undo, &mut buf, "c");
+
+        let rows = undo
+            .rows()
+            .into_iter()
+            .map(|r| (r.id, r.depth, r.current))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![(0, 0, false), (1, 0, false), (3, 1, true), (2, 0, false)]
+        );
+    }
+
+    #[test]
+    fn test_undo_group_1() {
+        let mut buf = Buffer::init(String::from("abc"));
+        let mut undo = UndoManager::new();
+        insert(&mut undo, &mut buf, "x");
+        undo.checkpoint();
+
+        undo.begin_group();
+        buf.jump(4);
+        let deleted = buf.delete_range(1..4);
+        undo.push(Command::DeleteAfter((1, deleted)), 4, 1);
+        undo.begin_group();
+        insert(&mut undo, &mut buf, "yz");
+        undo.end_group();
+        insert(&mut undo, &mut buf, "!");
+        undo.end_group();
+        assert_eq!(buf.to_string(), "xyz!");
+
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "xabc");
+        undo.redo(&mut buf);
+        assert_eq!(buf.to_string(), "xyz!");
+        undo.undo(&mut buf);
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "abc");
+    }
+
+    #[test]
+    fn test_undo_group_2() {
+        let mut buf = Buffer::init(String::new());
+        let mut undo = UndoManager::new();
+        insert(&mut undo, &mut buf, "a");
+
+        // a group of one command merges like a plain push, an empty one does nothing
+        undo.begin_group();
+        insert(&mut undo, &mut buf, "b");
+        undo.end_group();
+        undo.begin_group();
+        undo.end_group();
+
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "");
+        assert_eq!(undo.current(), 0);
+    }
+
+    #[test]
+    fn test_undo_cursor_1() {
+        let mut buf = Buffer::init(String::from("hello world"));
+        let mut undo = UndoManager::new();
+        buf.jump(11);
+        let deleted = buf.delete_range(6..11);
+        undo.push(Command::DeleteBefore((6, deleted)), 11, 6);
+        buf.jump(0);
+        insert(&mut undo, &mut buf, "oh ");
+        buf.jump(2);
+
+        undo.undo(&mut buf);
+        assert_eq!(buf.cursor(), 0);
+        undo.undo(&mut buf);
+        assert_eq!(buf.cursor(), 11);
+        undo.redo(&mut buf);
+        assert_eq!(buf.cursor(), 6);
+        undo.redo(&mut buf);
+        assert_eq!(buf.cursor(), 3);
+    }
+
+    #[test]
+    fn test_undo_checkpoint_1() {
+        let mut buf = Buffer::init(String::new());
+        let mut undo = UndoManager::new();
+        assert!(undo.is_at_checkpoint());
+
+        insert(&mut undo, &mut buf, "a");
+        assert!(!undo.is_at_checkpoint());
+        undo.checkpoint();
+        assert!(undo.is_at_checkpoint());
+
+        // typing right after a save does not merge into the saved state
+        insert(&mut undo, &mut buf, "b");
+        undo.undo(&mut buf);
+        assert!(undo.is_at_checkpoint());
+
+        insert(&mut undo, &mut buf, "c");
+        undo.switch_branch(&mut buf, true);
+        assert!(!undo.is_at_checkpoint());
+        undo.undo(&mut buf);
+        undo.undo(&mut buf);
+        assert!(!undo.is_at_checkpoint());
+    }
+
+    #[test]
+    fn test_undo_encode_1() {
+        let mut buf = Buffer::init(String::from("hello"));
+        let mut undo = UndoManager::new();
+        buf.jump(5);
+        insert(&mut undo, &mut buf, " wörld");
+        undo.checkpoint();
+        undo.begin_group();
+        let deleted = buf.delete_range(0..1);
+        undo.push(Command::DeleteAfter((0, deleted)), 0, 0);
+        insert(&mut undo, &mut buf, "J");
+        undo.end_group();
+        undo.undo(&mut buf);
+        buf.jump(12);
+        insert(&mut undo, &mut buf, "!");
+        undo.checkpoint();
+        undo.undo(&mut buf);
+
+        let mut decoded = UndoManager::decode(&undo.encode().unwrap(), 13).unwrap();
+        assert_eq!(decoded.rows(), {
+            undo.current = undo.saved.unwrap();
+            undo.rows()
+        });
+        assert!(decoded.is_at_checkpoint());
+
+        let mut buf = Buffer::init(String::from("hello wörld!"));
+        buf.jump(13);
+        decoded.switch_branch(&mut buf, true);
+        assert_eq!(buf.to_string(), "Jello wörld");
+        decoded.undo(&mut buf);
+        decoded.undo(&mut buf);
+        assert_eq!(buf.to_string(), "hello");
+    }
+
+    #[test]
+    fn test_undo_decode_1() {
+        let mut buf = Buffer::init(String::new());
+        let mut undo = UndoManager::new();
+        insert(&mut undo, &mut buf, "abc");
+        let bytes = undo.encode().unwrap();
+
+        assert!(UndoManager::decode(&bytes[..bytes.len() - 1], 0).is_none());
+        assert!(UndoManager::decode(&[bytes.as_slice(), b"x"].concat(), 0).is_none());
+        assert!(UndoManager::decode(b"not an undo file", 0).is_none());
+        assert!(UndoManager::decode(&bytes, 0).unwrap().nodes.len() == 2);
+    }
+
+    #[test]
+    fn test_undo_decode_2() {
+        let mut buf = Buffer::init(String::from("hello"));
+        let mut undo = UndoManager::new();
+        buf.jump(5);
+        insert(&mut undo, &mut buf, "!");
+        undo.checkpoint();
+        let bytes = undo.encode().unwrap();
+        assert!(UndoManager::decode(&bytes, 6).is_some());
+
+        // the text is shorter than the history says
+        assert!(UndoManager::decode(&bytes, 0).is_none());
+        assert!(UndoManager::decode(&bytes, 3).is_none());
+
+        // node count, saved state, then the parent, active branch and cursor of node 1
+        let node = MAGIC.len() + 2 * 8;
+        let mut active = bytes.clone();
+        active[node + 8..node + 16].copy_from_slice(&1u64.to_le_bytes());
+        assert!(UndoManager::decode(&active, 6).is_none());
+
+        let mut cursor = bytes;
+        cursor[node + 16..node + 24].copy_from_slice(&100u64.to_le_bytes());
+        assert!(UndoManager::decode(&cursor, 6).is_none());
+    }
+
+    #[test]
+    fn test_undo_limit_1() {
+        let mut buf = Buffer::init(String::new());
+        let mut undo = UndoManager::new();
+        undo.limit(3, usize::MAX);
+        for s in ["a", "b", "c", "d", "e"] {
+            insert(&mut undo, &mut buf, s);
+            undo.checkpoint();
+        }
+        undo.undo(&mut buf);
+        insert(&mut undo, &mut buf, "f");
+
+        // "a" then "b" are forgotten
+        assert_eq!(undo.rows().len(), 4);
+        assert!(undo.switch_branch(&mut buf, true));
+        assert!(undo.is_at_checkpoint());
+        assert!(undo.switch_branch(&mut buf, true));
+        (0..5).for_each(|_| undo.undo(&mut buf));
+        assert_eq!(buf.to_string(), "abc");
+
+        // then the branch with the saved state
+        undo.redo(&mut buf);
+        undo.limit(1, usize::MAX);
+        assert!(undo.encode().is_none());
+        assert!(!undo.switch_branch(&mut buf, true));
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "abcd");
+        assert!(!undo.is_at_checkpoint());
+    }
+
+    #[test]
+    fn test_undo_limit_2() {
+        let mut buf = Buffer::init(String::new());
+        let mut undo = UndoManager::new();
+        insert(&mut undo, &mut buf, "hello");
+        undo.checkpoint();
+        insert(&mut undo, &mut buf, " world");
+        undo.undo(&mut buf);
+        insert(&mut undo, &mut buf, " there");
+        undo.checkpoint();
+        insert(&mut undo, &mut buf, "!");
+
+        // the branch the current state is not on goes first
+        undo.limit(3, usize::MAX);
+        assert!(!undo.switch_branch(&mut buf, true));
+        assert_eq!(undo.rows().len(), 4);
+
+        undo.limit(usize::MAX, 7);
+        assert_eq!(undo.rows().len(), 3);
+        undo.undo(&mut buf);
+        undo.undo(&mut buf);
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "hello");
+        assert_eq!(undo.arena.live, 7);
+    }
+
+    #[test]
+    fn test_undo_limit_3() {
+        let mut buf = Buffer::init(String::new());
+        let mut undo = UndoManager::new();
+        undo.limit(20, usize::MAX);
+        for _ in 0..21 {
+            insert(&mut undo, &mut buf, "a");
+            undo.boundary();
+        }
+
+        // going over the limit drops 10% more, the next pushes do not drop anything
+        assert_eq!(undo.nodes.len() - 1, 18);
+        insert(&mut undo, &mut buf, "b");
+        undo.boundary();
+        insert(&mut undo, &mut buf, "c");
+        assert_eq!(undo.nodes.len() - 1, 20);
+        (0..20).for_each(|_| undo.undo(&mut buf));
+        assert_eq!(buf.to_string(), "aaa");
+    }
+
+    #[test]
+    fn test_undo_arena_1() {
+        let mut buf = Buffer::init(String::from("hello wörld"));
+        let mut undo = UndoManager::new();
+        undo.coalesce(Coalesce {
+            boundary: Boundary::Word,
+            ..Coalesce::ADJACENT
+        });
+        buf.jump(12);
+        for _ in 0..7 {
+            let bytes = buf.delete_grapheme_before_ptr().unwrap();
+            undo.push(Command::DeleteBefore((buf.cursor(), bytes)), 0, 0);
+        }
+
+        // "wörld" then "o ", the word boundary is found at the front of the run
+        assert_eq!(undo.nodes.len(), 3);
+        undo.checkpoint();
+        let mut decoded = UndoManager::decode(&undo.encode().unwrap(), 4).unwrap();
+        decoded.undo(&mut buf);
+        assert_eq!(buf.to_string(), "hello ");
+        decoded.undo(&mut buf);
+        assert_eq!(buf.to_string(), "hello wörld");
+        decoded.redo(&mut buf);
+        decoded.redo(&mut buf);
+        assert_eq!(buf.to_string(), "hell");
+
+        undo.undo(&mut buf);
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "hello wörld");
+    }
+
+    #[test]
+    fn test_undo_arena_2() {
+        let mut buf = Buffer::init(String::new());
+        let mut undo = UndoManager::new();
+        insert(&mut undo, &mut buf, &"a".repeat(100));
+        undo.undo(&mut buf);
+        insert(&mut undo, &mut buf, "b");
+
+        // typing again on a branch moves its change to the end of the arena
+        for s in ["a", "b", "a"] {
+            undo.switch_branch(&mut buf, true);
+            insert(&mut undo, &mut buf, s);
+        }
+        assert_eq!(undo.arena.live, 104);
+        assert!(undo.arena.bytes.len() <= 2 * undo.arena.live);
+
+        assert_eq!(buf.to_string(), "a".repeat(102));
+        undo.switch_branch(&mut buf, true);
+        assert_eq!(buf.to_string(), "bb");
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "");
+    }
+
+    #[test]
+    fn test_undo_arena_3() {
+        let mut buf = Buffer::init(String::from("hello wörld"));
+        let mut undo = UndoManager::new();
+        undo.limit(usize::MAX, 16);
+        buf.jump(13);
+        for _ in 0..6 {
+            let bytes = buf.delete_grapheme_before_ptr().unwrap();
+            undo.push(Command::DeleteBefore((buf.cursor(), bytes)), 0, 0);
+        }
+        // the backspaced text is kept back to front
+        let deleted = " wörld".bytes().rev().collect::<Vec<u8>>();
+        assert_eq!(undo.arena.bytes, deleted);
+        undo.checkpoint();
+
+        // a change is grown in place while at the end of the arena, moved to its end otherwise
+        insert(&mut undo, &mut buf, "12");
+        undo.undo(&mut buf);
+        insert(&mut undo, &mut buf, "34");
+        undo.switch_branch(&mut buf, false);
+        insert(&mut undo, &mut buf, "5");
+        insert(&mut undo, &mut buf, "6789");
+        assert_eq!(undo.arena.bytes, [&deleted[..], b"12341256789"].concat());
+        assert_eq!(undo.arena.live, 16);
+
+        // the holes are squeezed out once the deletion and the other branch are forgotten
+        insert(&mut undo, &mut buf, "0");
+        assert_eq!(undo.rows().len(), 3);
+        undo.limit(usize::MAX, 9);
+        assert_eq!(undo.rows().len(), 2);
+        assert_eq!(undo.arena.bytes, b"12567890");
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "hello");
+        assert!(!undo.switch_branch(&mut buf, true));
+    }
+
+    #[test]
+    fn test_undo_coalesce_1() {
+        let mut buf = Buffer::init(String::new());
+        let mut undo = UndoManager::new();
+        undo.coalesce(Coalesce {
+            boundary: Boundary::Word,
+            ..Coalesce::ADJACENT
+        });
+        for s in "hi  there\nyou".split_inclusive(|_| true) {
+            insert(&mut undo, &mut buf, s);
+        }
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "hi  there\n");
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "hi  ");
+
+        // deleting backward breaks at the same places
+        undo.redo(&mut buf);
+        for _ in 0..7 {
+            let bytes = buf.delete_grapheme_before_ptr().unwrap();
+            undo.push(Command::DeleteBefore((buf.cursor(), bytes)), 0, 0);
+        }
+        assert_eq!(buf.to_string(), "hi ");
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "hi  ");
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "hi  there\n");
+
+        undo.coalesce(Coalesce {
+            boundary: Boundary::Line,
+            ..Coalesce::ADJACENT
+        });
+        buf.jump(buf.len());
+        insert(&mut undo, &mut buf, "a b");
+        insert(&mut undo, &mut buf, "\n");
+        insert(&mut undo, &mut buf, "c");
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "hi  there\na b\n");
+    }
+
+    #[test]
+    fn test_undo_coalesce_2() {
+        let mut buf = Buffer::init(String::new());
+        let mut undo = UndoManager::new();
+        undo.coalesce(Coalesce {
+            idle: Some(Duration::from_secs(1)),
+            on_move: true,
+            ..Coalesce::ADJACENT
+        });
+        insert(&mut undo, &mut buf, "a");
+        insert(&mut undo, &mut buf, "b");
+        undo.nodes[undo.current].time -= Duration::from_secs(2);
+        insert(&mut undo, &mut buf, "c");
+        undo.moved();
+        insert(&mut undo, &mut buf, "d");
+        assert_eq!(undo.rows().len(), 4);
+
+        undo.undo(&mut buf);
+        undo.undo(&mut buf);
+        assert_eq!(buf.to_string(), "ab");
+        assert!(!undo.is_at_checkpoint());
     }
 }
@@ -0,0 +1,132 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{self, PathBuf};
+
+use crate::config::UndoFile;
+use crate::undo::UndoManager;
+
+// The undo history of a file, kept between sessions. It starts with a header line
+// holding the hash of the file contents it was saved with and the file path:
+//
+// 5d1c9e3f0a7b2c44	/home/me/notes.txt
+// <UndoManager::encode>
+//
+// so that a file changed by something else in between does not get a history that
+// does not match its text.
+
+// FNV-1a, good enough to notice that a file changed
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// where the history of the file at `path` is kept, none when it is not kept at all
+fn location(path: &str, mode: UndoFile) -> Option<PathBuf> {
+    let path = path::absolute(path).ok()?;
+
+    match mode {
+        UndoFile::Off => None,
+        UndoFile::Sidecar => {
+            let name = path.file_name()?.to_string_lossy();
+            Some(path.with_file_name(format!(".{}.kame-undo", name)))
+        }
+        UndoFile::Cache => {
+            let cache = env::var_os("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+            let key = hash(path.as_os_str().as_encoded_bytes());
+
+            Some(
+                cache
+                    .join("kame")
+                    .join("undo")
+                    .join(format!("{:016x}", key)),
+            )
+        }
+    }
+}
+
+fn header(path: &str, contents_hash: u64) -> String {
+    let path = path::absolute(path).unwrap_or_else(|_| PathBuf::from(path));
+    format!("{:016x}\t{}\n", contents_hash, path.display())
+}
+
+// `contents_hash` is the hash of the file as it is on disk, i.e. at the saved state of `undo`
+// a history that lost its saved state is not stored, the one stored with that save is still good
+pub fn store(path: &str, mode: UndoFile, contents_hash: u64, undo: &UndoManager) -> io::Result<()> {
+    let (Some(location), Some(history)) = (location(path, mode), undo.encode()) else {
+        return Ok(());
+    };
+
+    if let Some(dir) = location.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut bytes = header(path, contents_hash).into_bytes();
+    bytes.extend(history);
+
+    fs::write(location, bytes)
+}
+
+// the stored history, if there is one and it was made for these exact contents, which
+// are `len` bytes once decoded
+// a stale or corrupt one is deleted
+pub fn load(path: &str, mode: UndoFile, contents_hash: u64, len: usize) -> Option<UndoManager> {
+    let location = location(path, mode)?;
+    let bytes = fs::read(&location).ok()?;
+
+    let undo = bytes
+        .strip_prefix(header(path, contents_hash).as_bytes())
+        .and_then(|history| UndoManager::decode(history, len));
+    if undo.is_none() {
+        let _ = fs::remove_file(&location);
+    }
+
+    undo
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::buffer::Buffer;
+    use crate::storage::TextStorage;
+    use crate::undo::Command;
+
+    #[test]
+    fn test_undo_file_hash_1() {
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(hash(b"ab"), hash(b"ba"));
+    }
+
+    #[test]
+    fn test_undo_file_location_1() {
+        assert_eq!(location("tmp/a.txt", UndoFile::Off), None);
+
+        let sidecar = location("tmp/a.txt", UndoFile::Sidecar).unwrap();
+        assert!(sidecar.ends_with("tmp/.a.txt.kame-undo"));
+        assert!(sidecar.is_absolute());
+    }
+
+    #[test]
+    fn test_undo_file_store_1() {
+        let path = "tmp/test_undo_file_store_1.txt";
+        let mut buf = Buffer::init(String::new());
+        let mut undo = UndoManager::new();
+        buf.insert_str("abc");
+        undo.push(Command::Insert((0, String::from("abc"))), 0, 3);
+        undo.checkpoint();
+
+        store(path, UndoFile::Sidecar, hash(b"abc"), &undo).unwrap();
+        let mut loaded = load(path, UndoFile::Sidecar, hash(b"abc"), 3).unwrap();
+        loaded.undo(&mut buf);
+        assert_eq!(buf.to_string(), "");
+
+        // the file changed since, the history is dropped for good
+        assert!(load(path, UndoFile::Sidecar, hash(b"abcd"), 4).is_none());
+        assert!(load(path, UndoFile::Sidecar, hash(b"abc"), 3).is_none());
+    }
+}
@@ -1,6 +1,8 @@
 use std::rc::Rc;
+use std::time::{Duration, SystemTime};
 
 use crate::editor::Editor;
+use crate::width;
 
 use ratatui::prelude::*;
 use ratatui::{
@@ -34,38 +36,113 @@ impl View {
         // main
         let point = editor.get_current_point();
         let main_rect = self.layout[0];
-        let mut cursor = editor.make_cursor_visible(point, main_rect.height as usize);
-        let representer = editor.viewable_contents(main_rect.height as usize);
+        let (width, height) = (main_rect.width as usize, main_rect.height as usize);
+        let mut cursor = editor.make_cursor_visible(point, width, height);
+        let representer = editor.viewable_contents(width, height);
         let contents =
             Paragraph::new(representer.decorate()).style(Style::new().black().on_white());
         frame.render_widget(contents, self.layout[0]);
 
         // mode line
-        let changes = if editor.is_modified() {
-            "(modified)"
-        } else if editor.is_saved() {
-            "(saved)"
-        } else {
-            ""
-        };
-        let text = format!("~:~~ {}  L{}  {}", editor.path, point.1, changes);
+        let text = mode_line(editor, point.line);
         let contents = Paragraph::new(text).style(Style::new().white().on_blue().italic());
         frame.render_widget(contents, self.layout[1]);
 
         // prompt line
         let cmd_prompt_style = Style::new().black().on_white();
-        let contents = if editor.is_prompted() {
-            let prompt = format!(" search {}", editor.current_isearch_term());
+        let contents = if let Some(prompt) = editor.minibuffer_prompt() {
             cursor = (
-                prompt.len() as u16,
+                width::advance(&prompt, 0) as u16,
+                main_rect.height + self.layout[1].height,
+            );
+            match editor.minibuffer_completions() {
+                Some(completions) => Paragraph::new(format!("{}  {}", prompt, completions)),
+                None => Paragraph::new(prompt),
+            }
+        } else if editor.is_prompted() {
+            let scope = if editor.is_search_in_region() {
+                "in region "
+            } else {
+                ""
+            };
+            let prompt = format!(" search {}{}", scope, editor.current_isearch_term());
+            cursor = (
+                width::advance(&prompt, 0) as u16,
                 main_rect.height + self.layout[1].height,
             );
             Paragraph::new(prompt)
+        } else if let Some(echo) = editor.echo() {
+            Paragraph::new(format!(" {}", echo))
         } else {
             Paragraph::new("")
         };
         frame.render_widget(contents.style(cmd_prompt_style), self.layout[2]);
 
+        // undo tree browser, on the right so that the previewed text stays visible
+        if let Some(rows) = editor.undo_tree() {
+            let [_, a] =
+                Layout::horizontal([Constraint::Fill(2), Constraint::Fill(1)]).areas(main_rect);
+            let height = a.height.saturating_sub(2) as usize;
+            let current = rows.iter().position(|r| r.current).unwrap_or(0);
+            let skip = (current + 1).saturating_sub(height);
+            let now = SystemTime::now();
+            let lines = rows
+                .iter()
+                .skip(skip)
+                .take(height)
+                .map(|r| {
+                    let marker = if r.current { '*' } else { 'o' };
+                    let age = if r.id == 0 {
+                        String::from("original")
+                    } else {
+                        format!(
+                            "{} ago",
+                            age(now.duration_since(r.time).unwrap_or_default())
+                        )
+                    };
+                    let line = Line::from(format!(
+                        "{}{} {}  {}",
+                        "  ".repeat(r.depth),
+                        marker,
+                        r.id,
+                        age
+                    ));
+                    if r.current { line.reversed() } else { line }
+                })
+                .collect::<Vec<Line>>();
+            let p = Paragraph::new(lines)
+                .block(Block::bordered().title(" undo tree "))
+                .style(Style::new().white().on_light_blue());
+            frame.render_widget(Clear, a);
+            frame.render_widget(p, a);
+        }
+
+        // buffer list, over the text
+        if let Some(rows) = editor.buffer_list() {
+            let name_w = rows
+                .iter()
+                .map(|r| r.name.chars().count())
+                .max()
+                .unwrap_or(0);
+            let header =
+                Line::from(format!("  {:<name_w$}  {:>9}  {}", "name", "size", "path")).bold();
+            let lines = std::iter::once(header)
+                .chain(rows.iter().map(|r| {
+                    let modified = if r.modified { '*' } else { ' ' };
+                    let line = Line::from(format!(
+                        "{} {:<name_w$}  {:>9}  {}",
+                        modified, r.name, r.size, r.path
+                    ));
+                    if r.selected { line.reversed() } else { line }
+                }))
+                .collect::<Vec<Line>>();
+            let p = Paragraph::new(lines)
+                .block(Block::bordered().title(" buffers "))
+                .style(Style::new().white().on_light_blue());
+            frame.render_widget(Clear, main_rect);
+            frame.render_widget(p, main_rect);
+        }
+
         // user manual popup
         if editor.is_in_manual_popup() {
             let a = self.center(
@@ -103,3 +180,57 @@ impl View {
         area
     }
 }
+
+// "12s", "5m", "3h", "2d"
+// the line number counts from 1, the same as goto-line
+fn mode_line(editor: &Editor, line: usize) -> String {
+    let changes = if editor.is_modified() {
+        "(modified)"
+    } else if editor.is_saved() {
+        "(saved)"
+    } else {
+        ""
+    };
+    let wrap = if editor.is_wrapping() { "Wrap  " } else { "" };
+
+    format!(
+        "~:~~ {}  L{}  {}[{}]  {}{}",
+        editor.path,
+        line + 1,
+        editor.encoding.name(),
+        editor.line_ending.name(),
+        wrap,
+        changes
+    )
+}
+
+fn age(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::message::Message;
+
+    #[test]
+    fn test_view_mode_line_1() {
+        let mut editor = Editor::new(String::from("ab\ncd"), String::from("test_tmp"));
+        assert_eq!(mode_line(&editor, 0), "~:~~ test_tmp  L1  utf-8[lf]  ");
+
+        editor.update(Message::JumpToNextLine);
+        editor.update(Message::Insert('x'));
+        let line = editor.get_current_point().line;
+        assert_eq!(
+            mode_line(&editor, line),
+            "~:~~ test_tmp  L2  utf-8[lf]  (modified)"
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::borrow::Cow;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+pub const TAB_WIDTH: usize = 4;
+
+// number of terminal cells taken by the grapheme cluster `g` when drawn at column `col`
+// wide (East Asian, emoji) clusters take 2 cells, combining marks add nothing to
+// their base char and a tab stretches to the next tab stop
+pub fn grapheme_width(g: &str, col: usize) -> usize {
+    if g == "\t" {
+        TAB_WIDTH - col % TAB_WIDTH
+    } else {
+        g.width()
+    }
+}
+
+// the display column reached after drawing `s` from column `col`
+pub fn advance(s: &str, col: usize) -> usize {
+    s.graphemes(true)
+        .fold(col, |col, g| col + grapheme_width(g, col))
+}
+
+// byte offset of the first grapheme cluster of `s` that does not end before the
+// display column `col`, or `s.len()` when the line is shorter than that
+pub fn byte_at_col(s: &str, col: usize) -> usize {
+    let mut c = 0;
+    for (i, g) in s.grapheme_indices(true) {
+        let w = grapheme_width(g, c);
+        if c + w > col {
+            return i;
+        }
+
+        c += w;
+    }
+
+    s.len()
+}
+
+// `s` with its tabs expanded to spaces, as if it was drawn from column `*col`
+// `*col` is moved past `s`, a newline resets it
+pub fn expand_tabs<'a>(s: &'a str, col: &mut usize) -> Cow<'a, str> {
+    if !s.contains('\t') {
+        *col = match s.rfind('\n') {
+            Some(i) => advance(&s[i + 1..], 0),
+            None => advance(s, *col),
+        };
+
+        return Cow::Borrowed(s);
+    }
+
+    let mut res = String::with_capacity(s.len());
+    for g in s.graphemes(true) {
+        let w = grapheme_width(g, *col);
+        if g == "\t" {
+            res.extend(std::iter::repeat_n(' ', w));
+        } else {
+            res.push_str(g);
+        }
+
+        *col = if g.ends_with('\n') { 0 } else { *col + w };
+    }
+
+    Cow::Owned(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_width_advance_1() {
+        assert_eq!(advance("abc", 0), 3);
+        assert_eq!(advance("日本", 0), 4);
+        assert_eq!(advance("🧑\u{200D}🌾", 0), 2);
+        assert_eq!(advance("e\u{301}", 0), 1);
+        assert_eq!(advance("\u{301}", 0), 0);
+    }
+
+    #[test]
+    fn test_width_advance_2() {
+        assert_eq!(advance("\t", 0), TAB_WIDTH);
+        assert_eq!(advance("a\t", 0), TAB_WIDTH);
+        assert_eq!(advance("a\tb", 0), TAB_WIDTH + 1);
+        assert_eq!(advance("\t", 1), TAB_WIDTH);
+    }
+
+    #[test]
+    fn test_width_byte_at_col_1() {
+        let s = "a日b";
+        assert_eq!(byte_at_col(s, 0), 0);
+        assert_eq!(byte_at_col(s, 1), 1);
+        assert_eq!(byte_at_col(s, 2), 1);
+        assert_eq!(byte_at_col(s, 3), 4);
+        assert_eq!(byte_at_col(s, 4), 5);
+        assert_eq!(byte_at_col(s, 42), 5);
+    }
+
+    #[test]
+    fn test_width_expand_tabs_1() {
+        let mut col = 0;
+        assert_eq!(expand_tabs("ab", &mut col), "ab");
+        assert_eq!(col, 2);
+
+        assert_eq!(expand_tabs("\tc", &mut col), "  c");
+        assert_eq!(col, 5);
+
+        assert_eq!(expand_tabs("x\n\ty", &mut col), "x\n    y");
+        assert_eq!(col, 5);
+
+        assert_eq!(expand_tabs("z\n", &mut col), "z\n");
+        assert_eq!(col, 0);
+    }
+}
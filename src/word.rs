@@ -0,0 +1,164 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+// What word commands consider a word: letters, digits and the `extra` chars.
+// With `subwords`, the humps of camelCase and the parts of snake_case are words
+// of their own:
+//
+//   snake_case   HTTPServer   fooBar2Baz
+//   -----|----   ----|-----   ---|------
+//
+// i.e. the extra chars separate words and a word ends before an uppercase letter
+// that follows a lowercase one or a digit, or that starts a new hump after an acronym.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WordRules {
+    pub extra: String,
+    pub subwords: bool,
+}
+
+impl Default for WordRules {
+    fn default() -> Self {
+        Self {
+            extra: String::from("_"),
+            subwords: false,
+        }
+    }
+}
+
+impl WordRules {
+    fn is_word(&self, c: char) -> bool {
+        c.is_alphanumeric() || (!self.subwords && self.extra.contains(c))
+    }
+
+    // whether a subword starts at `c`, between `prev` and `next`
+    fn is_hump(&self, prev: char, c: char, next: Option<char>) -> bool {
+        self.subwords
+            && c.is_uppercase()
+            && (prev.is_lowercase()
+                || prev.is_numeric()
+                || (prev.is_uppercase() && next.is_some_and(char::is_lowercase)))
+    }
+
+    // the end of the word at or after `i`
+    pub fn forward(&self, text: &str, i: usize) -> usize {
+        let mut clusters = text[i..]
+            .grapheme_indices(true)
+            .map(|(j, g)| (i + j, first_char(g)))
+            .peekable();
+        while clusters.next_if(|(_, c)| !self.is_word(*c)).is_some() {}
+
+        let mut prev = None;
+        while let Some((j, c)) = clusters.next() {
+            let next = clusters.peek().map(|(_, c)| *c);
+            if !self.is_word(c) || prev.is_some_and(|p| self.is_hump(p, c, next)) {
+                return j;
+            }
+
+            prev = Some(c);
+        }
+
+        text.len()
+    }
+
+    // the start of the word before `i`
+    pub fn backward(&self, text: &str, i: usize) -> usize {
+        let mut clusters = text[..i]
+            .grapheme_indices(true)
+            .rev()
+            .map(|(j, g)| (j, first_char(g)))
+            .peekable();
+        while clusters.next_if(|(_, c)| !self.is_word(*c)).is_some() {}
+
+        // the leftmost cluster of the word so far, and the one after it
+        let mut start: Option<(usize, char)> = None;
+        let mut after = None;
+        while let Some(&(j, c)) = clusters.peek() {
+            if !self.is_word(c) {
+                break;
+            }
+            if let Some((k, s)) = start
+                && self.is_hump(c, s, after)
+            {
+                return k;
+            }
+
+            after = start.map(|(_, s)| s);
+            start = Some((j, c));
+            clusters.next();
+        }
+
+        start.map_or(0, |(k, _)| k)
+    }
+}
+
+// a cluster is classified by its base char, the marks combined with it do not count
+fn first_char(g: &str) -> char {
+    g.chars().next().expect("empty grapheme cluster")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_forward_1() {
+        let rules = WordRules::default();
+        let text = "  snake_case, HTTPServer\nfooBar";
+        assert_eq!(rules.forward(text, 0), 12);
+        assert_eq!(rules.forward(text, 4), 12);
+        assert_eq!(rules.forward(text, 12), 24);
+        assert_eq!(rules.forward(text, 24), 31);
+        assert_eq!(rules.forward(text, 31), 31);
+
+        let rules = WordRules {
+            subwords: true,
+            ..WordRules::default()
+        };
+        assert_eq!(rules.forward(text, 0), 7);
+        assert_eq!(rules.forward(text, 7), 12);
+        assert_eq!(rules.forward(text, 12), 18);
+        assert_eq!(rules.forward(text, 18), 24);
+        assert_eq!(rules.forward(text, 24), 28);
+        assert_eq!(rules.forward("fooBar2Baz", 3), 7);
+        assert_eq!(rules.forward("héÉllo", 0), 3);
+    }
+
+    #[test]
+    fn test_word_backward_1() {
+        let rules = WordRules::default();
+        let text = "  snake_case, HTTPServer\nfooBar";
+        assert_eq!(rules.backward(text, 31), 25);
+        assert_eq!(rules.backward(text, 25), 14);
+        assert_eq!(rules.backward(text, 14), 2);
+        assert_eq!(rules.backward(text, 2), 0);
+
+        let rules = WordRules {
+            subwords: true,
+            extra: String::from("_-"),
+        };
+        assert_eq!(rules.backward(text, 31), 28);
+        assert_eq!(rules.backward(text, 28), 25);
+        assert_eq!(rules.backward(text, 25), 18);
+        assert_eq!(rules.backward(text, 18), 14);
+        assert_eq!(rules.backward(text, 14), 8);
+        assert_eq!(rules.backward(text, 8), 2);
+        assert_eq!(rules.backward("fooBar2Baz", 10), 7);
+        assert_eq!(rules.backward("fooBar2Baz", 7), 3);
+    }
+
+    #[test]
+    fn test_word_combining_marks_1() {
+        // e + combining acute is one cluster, the accent does not end the word
+        let rules = WordRules::default();
+        let text = "cafe\u{301} x";
+        assert_eq!(rules.forward(text, 0), 6);
+        assert_eq!(rules.backward(text, 6), 0);
+        assert_eq!(rules.backward(text, 8), 7);
+
+        let rules = WordRules {
+            subwords: true,
+            ..WordRules::default()
+        };
+        assert_eq!(rules.forward("cafe\u{301}Bar", 0), 6);
+        assert_eq!(rules.backward("cafe\u{301}Bar", 9), 6);
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::width;
+
+// lines remembered at most, scrolling through a long file does not keep them all
+const MAX_CACHED_LINES: usize = 4096;
+
+// The row starts of the lines looked at since the text or the width last changed,
+// so walking rows one by one does not segment the same line over and over.
+#[derive(Debug, Default)]
+pub struct RowCache {
+    version: u64,
+    width: usize,
+    lines: HashMap<usize, Rc<[usize]>>,
+}
+
+impl RowCache {
+    // the row starts of `line` at this version of the text, `text` gives the line
+    // when they are not known yet
+    pub fn get(
+        &mut self,
+        line: usize,
+        version: u64,
+        width: usize,
+        text: impl FnOnce() -> String,
+    ) -> Rc<[usize]> {
+        if (version, width) != (self.version, self.width) || self.lines.len() >= MAX_CACHED_LINES {
+            self.lines.clear();
+            (self.version, self.width) = (version, width);
+        }
+
+        self.lines
+            .entry(line)
+            .or_insert_with(|| row_starts(&text(), width).into())
+            .clone()
+    }
+
+    // the versions of another text say nothing about this one
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+}
+
+// Where the screen rows of a line start when it is wrapped to `width` columns, as
+// byte offsets into the line, the first one being 0:
+//
+//   the quick brown fox     width 10:   the quick |
+//   ^         ^                         brown fox
+//
+// A row ends after the last whitespace that fits, or at the last char that fits
+// when a word is longer than the whole row. Whitespace never starts a row, it stays
+// at the end of the previous one even past the edge. Tabs are expanded from the start
+// of their row.
+pub fn row_starts(line: &str, width: usize) -> Vec<usize> {
+    let mut starts = vec![0];
+    if width == 0 {
+        return starts;
+    }
+
+    let mut col = 0;
+    // where the row can be broken, after whitespace
+    let mut last_break = None;
+    for (i, g) in line.grapheme_indices(true) {
+        let blank = g.chars().all(char::is_whitespace);
+        let mut w = width::grapheme_width(g, col);
+
+        if !blank && col + w > width && i > *starts.last().unwrap_or(&0) {
+            let at = last_break.take().unwrap_or(i);
+            starts.push(at);
+            col = width::advance(&line[at..i], 0);
+            w = width::grapheme_width(g, col);
+        }
+
+        col += w;
+        if blank {
+            last_break = Some(i + g.len());
+        }
+    }
+
+    starts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_row_starts_1() {
+        assert_eq!(row_starts("", 10), vec![0]);
+        assert_eq!(row_starts("the quick brown fox", 10), vec![0, 10]);
+        assert_eq!(row_starts("the quick brown fox", 9), vec![0, 10]);
+        assert_eq!(row_starts("the quick brown fox", 8), vec![0, 4, 10, 16]);
+        assert_eq!(row_starts("abcdefghij", 4), vec![0, 4, 8]);
+        assert_eq!(row_starts("ab cdefghij", 4), vec![0, 3, 7]);
+        assert_eq!(row_starts("abcd    efgh", 4), vec![0, 8]);
+        assert_eq!(row_starts("abc", 0), vec![0]);
+    }
+
+    #[test]
+    fn test_wrap_row_cache_1() {
+        let mut cache = RowCache::default();
+        let a = cache.get(3, 1, 10, || String::from("the quick brown fox"));
+        let b = cache.get(3, 1, 10, || unreachable!());
+        assert!(Rc::ptr_eq(&a, &b));
+        assert_eq!(*a, [0, 10]);
+
+        // a new version or width computes them again
+        assert_eq!(*cache.get(3, 2, 10, || String::from("fox")), [0]);
+        assert_eq!(
+            *cache.get(3, 2, 8, || String::from("the quick brown fox")),
+            [0, 4, 10, 16]
+        );
+        cache.clear();
+        assert_eq!(*cache.get(3, 2, 8, String::new), [0]);
+    }
+
+    #[test]
+    fn test_wrap_row_starts_2() {
+        // wide chars are not split, tabs restart from the row
+        assert_eq!(row_starts("日本語です", 5), vec![0, 6, 12]);
+        assert_eq!(row_starts("a\tb c\td", 6), vec![0, 4]);
+    }
+}